@@ -0,0 +1,229 @@
+use clap::Subcommand;
+use reqwest::Url;
+use rustyline::error::ReadlineError;
+
+use rust_requester::callback;
+use rust_requester::configuration::Configuration;
+use rust_requester::error::Error;
+use rust_requester::fetch;
+use rust_requester::provider;
+use rust_requester::request::{GmailLabelRequest, Unauthorized};
+use rust_requester::token::{Status, Token};
+
+use crate::{config, Context};
+
+#[derive(Subcommand, Debug)]
+pub enum AuthCommand {
+    /// Authorize in the browser and store the resulting token.
+    Login {
+        /// Don't listen for the browser's redirect. Print the URL, then paste
+        /// back the address the browser ends up on, or just the code. For
+        /// machines the browser can't reach, like over SSH.
+        #[arg(long, conflicts_with = "device")]
+        manual: bool,
+
+        /// Use the device flow: enter a short code on any other device
+        /// instead of being redirected back here. Custom providers need a
+        /// device auth URL.
+        #[arg(long)]
+        device: bool,
+    },
+    /// Show whether a token is stored, when it expires and was last
+    /// refreshed, and which scopes were granted against those requested.
+    Status,
+    /// Revoke the stored token at the provider, then forget it.
+    Logout {
+        /// Only forget the token here, without revoking it.
+        #[arg(long)]
+        local: bool,
+    },
+}
+
+/// The human version of `auth status`. JSON output gets every field.
+fn describe(status: &Status) -> String {
+    if !status.authorized {
+        return "Not authorized. Run `auth login`.".to_string();
+    }
+
+    let stored = match &status.account {
+        Some(account) => format!("Token stored for {}.", account),
+        None => "Token stored.".to_string(),
+    };
+    let expiry = match status.expires_in_seconds {
+        Some(_) if status.expired => "Access token has expired.".to_string(),
+        Some(seconds) => format!("Access token expires in {} seconds.", seconds),
+        None => "Access token has no stated expiry.".to_string(),
+    };
+    let refreshed = match status.seconds_since_refresh() {
+        Some(seconds) => format!("Last refreshed {} seconds ago.", seconds),
+        None => "Last refresh time unknown.".to_string(),
+    };
+    let refresh = if status.has_refresh_token {
+        "Refresh token stored."
+    } else {
+        "No refresh token, `auth login` will be needed once it expires."
+    };
+    let granted = if status.granted_scopes.is_empty() {
+        "Granted scopes: as requested (the provider didn't list them).".to_string()
+    } else {
+        format!("Granted scopes: {}", status.granted_scopes.join(" "))
+    };
+    let requested = format!("Requested scopes: {}", status.requested_scopes.join(" "));
+
+    let mut lines = vec![stored, expiry, refreshed, refresh.to_string(), requested, granted];
+    if !status.missing_scopes.is_empty() {
+        lines.push(format!(
+            "Not granted: {}. Run `auth login` to consent again.",
+            status.missing_scopes.join(" ")
+        ));
+    }
+    lines.join("\n")
+}
+
+pub fn run(command: AuthCommand, config: Configuration, ctx: &Context) -> Result<(), Error> {
+    match command {
+        AuthCommand::Login { manual, device } => {
+            // Every flow but the offline one needs a person at a browser.
+            if ctx.non_interactive && !ctx.offline {
+                return Err(Error::Configuration(
+                    "auth login needs someone to approve it, but --non-interactive is set"
+                        .to_string(),
+                ));
+            }
+            let mode = match (manual, device) {
+                (_, true) => LoginMode::Device,
+                _ if ctx.offline => LoginMode::Offline,
+                (true, _) => LoginMode::Manual,
+                _ => LoginMode::Loopback,
+            };
+            let config = config::require_complete(config, &ctx.db, ctx.non_interactive)?;
+            let provider = provider::from_configuration(&config);
+            let profile = config.profile.clone();
+            let request = ctx.block_on(async {
+                let request = login(ctx.request(&config)?, mode).await?;
+                fetch::verify_identity(request, &ctx.db).await
+            })?;
+            let token = request.token();
+            token.save(&ctx.db, &profile)?;
+            let human = match &token.email {
+                Some(email) => format!("Authorized as {}.", email),
+                None => "Authorized.".to_string(),
+            };
+            ctx.output.emit(&Status::new(Some(token), &*provider), &human)
+        }
+        AuthCommand::Status => {
+            let token = Token::load(&ctx.db, &config.profile)?;
+            let provider = provider::from_configuration(&config);
+            let status = Status::new(token.as_ref(), &*provider);
+            ctx.output.emit(&status, &describe(&status))
+        }
+        AuthCommand::Logout { local } => {
+            let token = Token::load(&ctx.db, &config.profile)?;
+            let human = match token {
+                Some(token) if !local => ctx.block_on(revoke(&config, token, ctx))?,
+                Some(_) => "Stored token removed.",
+                None => "No token stored.",
+            };
+            Token::delete(&ctx.db, &config.profile)?;
+            let provider = provider::from_configuration(&config);
+            ctx.output.emit(&Status::new(None, &*provider), human)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginMode {
+    Loopback,
+    Manual,
+    Device,
+    /// Nobody is coming back from a browser during a replay or the demo,
+    /// but then neither cares what the code is.
+    Offline,
+}
+
+/// The full loopback flow: print the URL, wait for the browser to come back
+/// to us, then trade the code for a token. In manual mode the user carries
+/// the code back instead, and no listener is started. The device flow skips
+/// redirects altogether.
+pub async fn login(
+    label_request: GmailLabelRequest<Unauthorized>,
+    mode: LoginMode,
+) -> Result<GmailLabelRequest, Error> {
+    if mode == LoginMode::Device {
+        let label_request = label_request.oauth2_device_authorization().await?;
+        let details = label_request.details();
+        eprintln!(
+            "On any device, visit {} and enter the code:\n\n    {}\n\nWaiting for approval...",
+            details.verification_uri().as_str(),
+            details.user_code().secret()
+        );
+        return label_request.oauth2_device_token().await;
+    }
+
+    if mode == LoginMode::Loopback {
+        return fetch::browser_login(label_request, print_auth_url).await;
+    }
+
+    if mode == LoginMode::Offline {
+        let code = String::from("REDACTED");
+        return label_request.authorize().oauth2_token_verification(code).await;
+    }
+
+    let label_request = label_request.authorize();
+    print_auth_url(label_request.auth_url());
+
+    eprintln!("\nOnce you've approved access, your browser will be sent to a localhost address that probably won't load. Copy that whole address, or just its code, and paste it here.");
+    let mut rl = rustyline::Editor::<()>::new().map_err(Box::from)?;
+    let pasted = match rl.readline("Redirect URL or code: ") {
+        Ok(pasted) => pasted,
+        Err(ReadlineError::Interrupted) => return Err(Error::Cancelled),
+        Err(err) => return Err(Error::from(Box::from(err))),
+    };
+    let code = label_request.parse_authorization_response(&pasted)?;
+    if callback::is_bare_code(&pasted) {
+        eprintln!("Warning: a bare code carries no state, so it couldn't be checked against this login. Paste the whole address to have it checked.");
+    }
+
+    label_request.oauth2_token_verification(code).await
+}
+
+fn print_auth_url(url: &Url) {
+    eprintln!("Please visit the following URL and follow the instructions to authorize this application:\n\n{}", url);
+}
+
+/// The library's stored token, refresh or login sequence, with the login
+/// done from the terminal. That needs someone at a browser, so
+/// non-interactive runs fail instead.
+pub async fn authorized_request(
+    config: Configuration,
+    ctx: &Context,
+) -> Result<GmailLabelRequest, Error> {
+    let request = ctx.request(&config)?;
+    fetch::authorized_request(request, &config.profile, &ctx.db, |request| async move {
+        match ctx.offline {
+            true => login(request, LoginMode::Offline).await,
+            false if ctx.non_interactive => Err(Error::MissingToken),
+            false => login(request, LoginMode::Loopback).await,
+        }
+    })
+    .await
+}
+
+/// A token that can't be revoked is still worth forgetting, so a provider
+/// without a revocation endpoint only gets a warning. A failed revocation
+/// is an error though, and leaves the token stored for another try.
+async fn revoke(
+    config: &Configuration,
+    token: Token,
+    ctx: &Context,
+) -> Result<&'static str, Error> {
+    let request = ctx.request(config)?.with_token(token);
+
+    if !request.can_revoke() {
+        eprintln!("No revocation endpoint configured, the token is only removed locally.");
+        return Ok("Stored token removed.");
+    }
+
+    request.revoke().await?;
+    Ok("Token revoked and removed.")
+}
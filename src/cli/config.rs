@@ -0,0 +1,579 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use rust_requester::configuration::{
+    client_auth::{ClientType, TokenAuthMethod},
+    client_secret::ClientSecret,
+    discovery::{self, Discovered, ProviderMetadata},
+    port::Port,
+    Configuration,
+};
+use rust_requester::db::{self, EditableConfigFields};
+use rust_requester::error::Error;
+use rust_requester::provider::ProviderKind;
+
+use crate::Context;
+
+/// Overrides for the stored configuration, so the CLI can run in CI and
+/// containers without prompting. Flags win over environment variables,
+/// which win over the database. Overrides are never saved.
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    /// Take credentials from a Google `client_secret.json`. The individual
+    /// field flags below still win over it.
+    #[arg(long, global = true, env = "RUST_REQUESTER_CLIENT_SECRET_FILE", value_name = "PATH")]
+    client_secret_file: Option<PathBuf>,
+
+    /// Which API to talk to: google, or custom for any other OAuth API.
+    #[arg(long, global = true, env = "RUST_REQUESTER_PROVIDER", value_parser = parse_provider)]
+    provider: Option<ProviderKind>,
+
+    /// Space separated scopes to request. Custom providers only.
+    #[arg(long, global = true, env = "RUST_REQUESTER_SCOPES")]
+    scopes: Option<String>,
+
+    /// The collection to list. Custom providers only.
+    #[arg(long, global = true, env = "RUST_REQUESTER_RESOURCE_URL")]
+    resource_url: Option<String>,
+
+    /// OpenID Connect issuer, to discover the endpoints from.
+    #[arg(long, global = true, env = "RUST_REQUESTER_ISSUER_URL")]
+    issuer_url: Option<String>,
+
+    /// OAuth client id.
+    #[arg(long, global = true, env = "RUST_REQUESTER_CLIENT_ID")]
+    client_id: Option<String>,
+
+    /// OAuth client secret. Not needed for public clients.
+    #[arg(long, global = true, env = "RUST_REQUESTER_CLIENT_SECRET", hide_env_values = true)]
+    client_secret: Option<String>,
+
+    /// Whether the client has a secret to send.
+    #[arg(
+        long,
+        global = true,
+        env = "RUST_REQUESTER_CLIENT_TYPE",
+        value_parser = parse_client_type
+    )]
+    client_type: Option<ClientType>,
+
+    /// How the client secret is sent to the token endpoint.
+    #[arg(
+        long,
+        global = true,
+        env = "RUST_REQUESTER_TOKEN_AUTH_METHOD",
+        value_parser = parse_token_auth_method
+    )]
+    token_auth_method: Option<TokenAuthMethod>,
+
+    /// OAuth authorization endpoint. Google's is used if unset.
+    #[arg(long, global = true, env = "RUST_REQUESTER_AUTH_URL")]
+    auth_url: Option<String>,
+
+    /// OAuth token endpoint. Google's is used if unset.
+    #[arg(long, global = true, env = "RUST_REQUESTER_TOKEN_URL")]
+    token_url: Option<String>,
+
+    /// OAuth device authorization endpoint, for `auth login --device`.
+    #[arg(long, global = true, env = "RUST_REQUESTER_DEVICE_AUTH_URL")]
+    device_auth_url: Option<String>,
+
+    /// OAuth token revocation endpoint.
+    #[arg(long, global = true, env = "RUST_REQUESTER_REVOCATION_URL")]
+    revocation_url: Option<String>,
+
+    /// OpenID Connect userinfo endpoint.
+    #[arg(long, global = true, env = "RUST_REQUESTER_USERINFO_URL")]
+    userinfo_url: Option<String>,
+
+    /// Where the issuer publishes its ID token signing keys.
+    #[arg(long, global = true, env = "RUST_REQUESTER_JWKS_URL")]
+    jwks_url: Option<String>,
+
+    /// Port for the loopback redirect listener.
+    #[arg(long, global = true, env = "RUST_REQUESTER_LOCAL_PORT")]
+    local_port: Option<u16>,
+
+    /// Seconds to wait for a connection to the server.
+    #[arg(long, global = true, env = "RUST_REQUESTER_CONNECT_TIMEOUT", value_name = "SECONDS")]
+    connect_timeout: Option<u64>,
+
+    /// Seconds a response can go quiet before giving up on it.
+    #[arg(long, global = true, env = "RUST_REQUESTER_READ_TIMEOUT", value_name = "SECONDS")]
+    read_timeout: Option<u64>,
+
+    /// User-Agent header to send.
+    #[arg(long, global = true, env = "RUST_REQUESTER_USER_AGENT")]
+    user_agent: Option<String>,
+
+    /// Proxy for all traffic, e.g. http://proxy.corp:3128. Without it,
+    /// HTTPS_PROXY and friends are still honored.
+    #[arg(long, global = true, env = "RUST_REQUESTER_PROXY_URL")]
+    proxy_url: Option<String>,
+
+    /// PEM file of extra CA certificates to trust, such as a corporate root.
+    #[arg(long, global = true, env = "RUST_REQUESTER_CA_BUNDLE", value_name = "PATH")]
+    ca_bundle: Option<PathBuf>,
+
+    /// Fail with a list of missing fields instead of prompting for them, and
+    /// never start a login that needs a browser.
+    #[arg(
+        long,
+        global = true,
+        env = "RUST_REQUESTER_NON_INTERACTIVE",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    pub non_interactive: bool,
+}
+
+impl ConfigArgs {
+    pub fn apply(&self, config: Configuration) -> Result<Configuration, Error> {
+        let mut config = match &self.client_secret_file {
+            Some(path) => ClientSecret::from_file(path)?.apply(config),
+            None => config,
+        };
+        if let Some(provider) = self.provider {
+            config.api.provider = provider;
+        }
+        if let Some(scopes) = &self.scopes {
+            config.api.scopes = Some(scopes.clone());
+        }
+        if let Some(resource_url) = &self.resource_url {
+            config.api.resource_url = Some(resource_url.clone());
+        }
+        if let Some(issuer_url) = &self.issuer_url {
+            config.api.issuer_url = Some(issuer_url.clone());
+        }
+        if let Some(id) = &self.client_id {
+            config.api.id = Some(id.clone());
+        }
+        if let Some(secret) = &self.client_secret {
+            config.api.secret = Some(secret.clone());
+        }
+        if let Some(client_type) = self.client_type {
+            config.api.client_type = client_type;
+        }
+        if let Some(token_auth_method) = self.token_auth_method {
+            config.api.token_auth_method = token_auth_method;
+        }
+        if let Some(auth_url) = &self.auth_url {
+            config.api.auth_url = Some(auth_url.clone());
+        }
+        if let Some(token_url) = &self.token_url {
+            config.api.token_url = Some(token_url.clone());
+        }
+        if let Some(device_auth_url) = &self.device_auth_url {
+            config.api.device_auth_url = Some(device_auth_url.clone());
+        }
+        if let Some(revocation_url) = &self.revocation_url {
+            config.api.revocation_url = Some(revocation_url.clone());
+        }
+        if let Some(userinfo_url) = &self.userinfo_url {
+            config.api.userinfo_url = Some(userinfo_url.clone());
+        }
+        if let Some(jwks_url) = &self.jwks_url {
+            config.api.jwks_url = Some(jwks_url.clone());
+        }
+        if let Some(local_port) = self.local_port {
+            config.local_port = Some(Port::from(local_port));
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            config.http.connect_timeout = Some(connect_timeout);
+        }
+        if let Some(read_timeout) = self.read_timeout {
+            config.http.read_timeout = Some(read_timeout);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            config.http.user_agent = Some(user_agent.clone());
+        }
+        if let Some(proxy_url) = &self.proxy_url {
+            config.http.proxy_url = Some(proxy_url.clone());
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            config.http.ca_bundle_path = Some(ca_bundle.display().to_string());
+        }
+        Ok(config)
+    }
+}
+
+/// With an issuer set, the discovered endpoints replace the stored ones for
+/// this run. Flags still win, so they're applied again on top.
+pub fn discover(
+    config: Configuration,
+    args: &ConfigArgs,
+    ctx: &Context,
+) -> Result<Configuration, Error> {
+    let issuer = match config.api.issuer_url.clone() {
+        Some(issuer) => issuer,
+        None => return Ok(config),
+    };
+
+    let transport = ctx.transport(&config)?;
+    let discovered = ctx.block_on(discovery::discover(&issuer, &ctx.db, false, transport))?;
+    args.apply(warn_if_stale(discovered).apply(config))
+}
+
+// Still worth carrying on with, but the user should know the endpoints
+// might be out of date.
+fn warn_if_stale(discovered: Discovered) -> ProviderMetadata {
+    if let Some(err) = &discovered.stale {
+        eprintln!("Could not refresh discovery document, using cached copy: {}", err);
+    }
+    discovered.metadata
+}
+
+// The library types parse themselves, these only list the choices in the
+// error, since clap can't enumerate them for a plain FromStr.
+fn parse_client_type(s: &str) -> Result<ClientType, String> {
+    s.parse()
+        .map_err(|err| format!("{} (expected one of: confidential, public)", err))
+}
+
+fn parse_provider(s: &str) -> Result<ProviderKind, String> {
+    s.parse()
+        .map_err(|err| format!("{} (expected one of: google, custom)", err))
+}
+
+fn parse_token_auth_method(s: &str) -> Result<TokenAuthMethod, String> {
+    s.parse().map_err(|err| {
+        format!("{} (expected one of: client_secret_basic, client_secret_post)", err)
+    })
+}
+
+fn parse_seconds(s: &str) -> Result<u64, Error> {
+    s.parse()
+        .map_err(|_| Error::Configuration(format!("{} is not a whole number of seconds", s)))
+}
+
+fn describe_field(field: EditableConfigFields) -> &'static str {
+    match field {
+        EditableConfigFields::ApiId => "client id (--client-id, RUST_REQUESTER_CLIENT_ID)",
+        EditableConfigFields::ApiSecret => {
+            "client secret (--client-secret, RUST_REQUESTER_CLIENT_SECRET)"
+        }
+        EditableConfigFields::AuthUrl => "auth url (--auth-url, RUST_REQUESTER_AUTH_URL)",
+        EditableConfigFields::TokenUrl => "token url (--token-url, RUST_REQUESTER_TOKEN_URL)",
+        EditableConfigFields::LocalPort => "local port (--local-port, RUST_REQUESTER_LOCAL_PORT)",
+        EditableConfigFields::DeviceAuthUrl => {
+            "device auth url (--device-auth-url, RUST_REQUESTER_DEVICE_AUTH_URL)"
+        }
+        EditableConfigFields::ClientType => {
+            "client type (--client-type, RUST_REQUESTER_CLIENT_TYPE)"
+        }
+        EditableConfigFields::TokenAuthMethod => {
+            "token auth method (--token-auth-method, RUST_REQUESTER_TOKEN_AUTH_METHOD)"
+        }
+        EditableConfigFields::Provider => "provider (--provider, RUST_REQUESTER_PROVIDER)",
+        EditableConfigFields::Scopes => "scopes (--scopes, RUST_REQUESTER_SCOPES)",
+        EditableConfigFields::ResourceUrl => {
+            "resource url (--resource-url, RUST_REQUESTER_RESOURCE_URL)"
+        }
+        EditableConfigFields::IssuerUrl => "issuer url (--issuer-url, RUST_REQUESTER_ISSUER_URL)",
+        EditableConfigFields::RevocationUrl => {
+            "revocation url (--revocation-url, RUST_REQUESTER_REVOCATION_URL)"
+        }
+        EditableConfigFields::UserinfoUrl => {
+            "userinfo url (--userinfo-url, RUST_REQUESTER_USERINFO_URL)"
+        }
+        EditableConfigFields::JwksUrl => "jwks url (--jwks-url, RUST_REQUESTER_JWKS_URL)",
+        EditableConfigFields::ConnectTimeout => {
+            "connect timeout (--connect-timeout, RUST_REQUESTER_CONNECT_TIMEOUT)"
+        }
+        EditableConfigFields::ReadTimeout => {
+            "read timeout (--read-timeout, RUST_REQUESTER_READ_TIMEOUT)"
+        }
+        EditableConfigFields::UserAgent => "user agent (--user-agent, RUST_REQUESTER_USER_AGENT)",
+        EditableConfigFields::ProxyUrl => "proxy url (--proxy-url, RUST_REQUESTER_PROXY_URL)",
+        EditableConfigFields::CaBundlePath => "CA bundle (--ca-bundle, RUST_REQUESTER_CA_BUNDLE)",
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the stored configuration. The client secret is masked.
+    Show,
+    /// Set a single configuration field.
+    Set { field: ConfigField, value: String },
+    /// Save the credentials from a Google `client_secret.json`.
+    Import { path: PathBuf },
+    /// List the profiles in the database.
+    Profiles,
+    /// Fetch the issuer's OpenID Connect discovery document and save the
+    /// endpoints it lists.
+    Discover {
+        /// Fetch it again even if the cached copy is still fresh.
+        #[arg(long)]
+        refresh: bool,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ConfigField {
+    ClientId,
+    ClientSecret,
+    AuthUrl,
+    TokenUrl,
+    LocalPort,
+    DeviceAuthUrl,
+    ClientType,
+    TokenAuthMethod,
+    Provider,
+    Scopes,
+    ResourceUrl,
+    IssuerUrl,
+    RevocationUrl,
+    UserinfoUrl,
+    JwksUrl,
+    ConnectTimeout,
+    ReadTimeout,
+    UserAgent,
+    ProxyUrl,
+    CaBundle,
+}
+
+/// The effective configuration as `config show` prints it. The secret is
+/// only ever reported as present or absent.
+#[derive(Serialize, Debug)]
+struct ConfigView {
+    profile: String,
+    provider: String,
+    scopes: Option<String>,
+    resource_url: Option<String>,
+    issuer_url: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<&'static str>,
+    client_type: String,
+    token_auth_method: String,
+    auth_url: Option<String>,
+    token_url: Option<String>,
+    device_auth_url: Option<String>,
+    revocation_url: Option<String>,
+    userinfo_url: Option<String>,
+    jwks_url: Option<String>,
+    local_port: Option<u16>,
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    user_agent: Option<String>,
+    proxy_url: Option<String>,
+    ca_bundle: Option<String>,
+}
+
+impl ConfigView {
+    fn new(config: &Configuration) -> Self {
+        Self {
+            profile: config.profile.clone(),
+            provider: config.api.provider.to_string(),
+            scopes: config.api.scopes.clone(),
+            resource_url: config.api.resource_url.clone(),
+            issuer_url: config.api.issuer_url.clone(),
+            client_id: config.api.id.clone(),
+            client_secret: config.api.secret.as_ref().map(|_| "********"),
+            client_type: config.api.client_type.to_string(),
+            token_auth_method: config.api.token_auth_method.to_string(),
+            auth_url: config.api.auth_url.clone(),
+            token_url: config.api.token_url.clone(),
+            device_auth_url: config.api.device_auth_url.clone(),
+            revocation_url: config.api.revocation_url.clone(),
+            userinfo_url: config.api.userinfo_url.clone(),
+            jwks_url: config.api.jwks_url.clone(),
+            local_port: config.local_port.map(Port::as_u16),
+            connect_timeout: config.http.connect_timeout,
+            read_timeout: config.http.read_timeout,
+            user_agent: config.http.user_agent.clone(),
+            proxy_url: config.http.proxy_url.clone(),
+            ca_bundle: config.http.ca_bundle_path.clone(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let field = |value: Option<String>| value.unwrap_or_else(|| "(unset)".to_string());
+        let seconds = |value: u64| format!("{}s", value);
+        [
+            format!("profile:           {}", self.profile),
+            format!("provider:          {}", self.provider),
+            format!("scopes:            {}", field(self.scopes.clone())),
+            format!("resource-url:      {}", field(self.resource_url.clone())),
+            format!("issuer-url:        {}", field(self.issuer_url.clone())),
+            format!("client-id:         {}", field(self.client_id.clone())),
+            format!("client-secret:     {}", field(self.client_secret.map(str::to_owned))),
+            format!("client-type:       {}", self.client_type),
+            format!("token-auth-method: {}", self.token_auth_method),
+            format!("auth-url:          {}", field(self.auth_url.clone())),
+            format!("token-url:         {}", field(self.token_url.clone())),
+            format!("device-auth-url:   {}", field(self.device_auth_url.clone())),
+            format!("revocation-url:    {}", field(self.revocation_url.clone())),
+            format!("userinfo-url:      {}", field(self.userinfo_url.clone())),
+            format!("jwks-url:          {}", field(self.jwks_url.clone())),
+            format!("local-port:        {}", field(self.local_port.map(|port| port.to_string()))),
+            format!("connect-timeout:   {}", field(self.connect_timeout.map(seconds))),
+            format!("read-timeout:      {}", field(self.read_timeout.map(seconds))),
+            format!("user-agent:        {}", field(self.user_agent.clone())),
+            format!("proxy-url:         {}", field(self.proxy_url.clone())),
+            format!("ca-bundle:         {}", field(self.ca_bundle.clone())),
+        ]
+        .join("\n")
+    }
+}
+
+pub fn run(command: ConfigCommand, mut config: Configuration, ctx: &Context) -> Result<(), Error> {
+    let db = &ctx.db;
+    match command {
+        ConfigCommand::Show => {
+            let view = ConfigView::new(&config);
+            ctx.output.emit(&view, &view.describe())
+        }
+        ConfigCommand::Set { field, value } => {
+            match field {
+                ConfigField::ClientId => config.update_id(db, value)?,
+                ConfigField::ClientSecret => config.update_secret(db, value)?,
+                ConfigField::AuthUrl => config.update_auth_url(db, value)?,
+                ConfigField::TokenUrl => config.update_token_url(db, value)?,
+                ConfigField::DeviceAuthUrl => config.update_device_auth_url(db, value)?,
+                ConfigField::ClientType => {
+                    let client_type = parse_client_type(&value).map_err(Error::Configuration)?;
+                    config.update_client_type(db, client_type)?
+                }
+                ConfigField::Provider => {
+                    let provider = parse_provider(&value).map_err(Error::Configuration)?;
+                    config.update_provider(db, provider)?
+                }
+                ConfigField::Scopes => config.update_scopes(db, value)?,
+                ConfigField::ResourceUrl => config.update_resource_url(db, value)?,
+                ConfigField::IssuerUrl => config.update_issuer_url(db, value)?,
+                ConfigField::RevocationUrl => config.update_revocation_url(db, value)?,
+                ConfigField::UserinfoUrl => config.update_userinfo_url(db, value)?,
+                ConfigField::JwksUrl => config.update_jwks_url(db, value)?,
+                ConfigField::TokenAuthMethod => {
+                    let method = parse_token_auth_method(&value).map_err(Error::Configuration)?;
+                    config.update_token_auth_method(db, method)?
+                }
+                ConfigField::LocalPort => {
+                    let port = value.parse::<u16>().map_err(|_| Error::InvalidPort)?;
+                    config.update_local_port(db, port)?
+                }
+                ConfigField::ConnectTimeout => {
+                    config.update_connect_timeout(db, Some(parse_seconds(&value)?))?
+                }
+                ConfigField::ReadTimeout => {
+                    config.update_read_timeout(db, Some(parse_seconds(&value)?))?
+                }
+                ConfigField::UserAgent => config.update_user_agent(db, value)?,
+                ConfigField::ProxyUrl => config.update_proxy_url(db, value)?,
+                ConfigField::CaBundle => config.update_ca_bundle_path(db, value)?,
+            }
+            let view = ConfigView::new(&Configuration::init(db, &config.profile)?);
+            ctx.output.emit(&view, "Saved.")
+        }
+        ConfigCommand::Import { path } => {
+            let imported = config.import_client_secret(&path, db)?;
+            let human = format!("Imported credentials from {}", path.display());
+            ctx.output.emit(&ConfigView::new(&imported), &human)
+        }
+        ConfigCommand::Discover { refresh } => {
+            let issuer = config.api.issuer_url.clone().ok_or_else(|| {
+                let field = describe_field(EditableConfigFields::IssuerUrl);
+                Error::Configuration(format!("missing {}", field))
+            })?;
+            let transport = ctx.transport(&config)?;
+            let discovered = ctx.block_on(discovery::discover(&issuer, db, refresh, transport))?;
+            let metadata = warn_if_stale(discovered);
+            let imported = config.import_discovery(&metadata, db)?;
+            let human = format!("Discovered endpoints for {}", issuer);
+            ctx.output.emit(&ConfigView::new(&imported), &human)
+        }
+        ConfigCommand::Profiles => {
+            let profiles = db::list_profiles(db)?;
+            let human = profiles
+                .iter()
+                .map(|profile| {
+                    let marker = if profile == &config.profile { "*" } else { " " };
+                    format!("{} {}", marker, profile)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            ctx.output.emit(&profiles, &human)
+        }
+    }
+}
+
+/// Prompt for anything missing, unless told not to, then refuse to go on if
+/// it's still missing.
+pub fn require_complete(
+    config: Configuration,
+    db: &db::Connection,
+    non_interactive: bool,
+) -> Result<Configuration, Error> {
+    let config = if config.is_complete() || non_interactive {
+        config
+    } else {
+        fill_config(config, db)?
+    };
+
+    let missing = config.api.missing_fields();
+    if !missing.is_empty() {
+        let missing: Vec<&str> = missing.into_iter().map(describe_field).collect();
+        return Err(Error::Configuration(format!(
+            "missing {}",
+            missing.join(", ")
+        )));
+    }
+
+    Ok(config)
+}
+
+fn prompt(rl: &mut rustyline::Editor<()>, prompt: &str) -> Option<String> {
+    rl.readline(prompt).ok().filter(|s| !s.is_empty())
+}
+
+/// Only the prompted values are saved. Anything that came from a flag or the
+/// environment is left out of the database.
+fn fill_config(
+    mut configuration: Configuration,
+    db: &db::Connection,
+) -> Result<Configuration, Error> {
+    println!("Please enter missing API credentials");
+    let mut rl = rustyline::Editor::<()>::new().map_err(Box::from)?;
+
+    if configuration.api.id.is_none() {
+        if let Some(id) = prompt(&mut rl, "API ID: ") {
+            configuration.update_id(db, id.clone())?;
+            configuration.api.id = Some(id);
+        }
+    }
+
+    let needs_secret = configuration.api.client_type == ClientType::Confidential;
+    if configuration.api.secret.is_none() && needs_secret {
+        if let Some(secret) = prompt(&mut rl, "API Secret: ") {
+            configuration.update_secret(db, secret.clone())?;
+            configuration.api.secret = Some(secret);
+        }
+    }
+
+    // Google's endpoints have defaults, so only ask for a custom provider's.
+    let custom = configuration.api.provider == ProviderKind::Custom;
+    if configuration.api.auth_url.is_none() && custom {
+        if let Some(auth_url) = prompt(&mut rl, "Auth URL: ") {
+            configuration.update_auth_url(db, auth_url.clone())?;
+            configuration.api.auth_url = Some(auth_url);
+        }
+    }
+
+    if configuration.api.token_url.is_none() && custom {
+        if let Some(token_url) = prompt(&mut rl, "Token URL: ") {
+            configuration.update_token_url(db, token_url.clone())?;
+            configuration.api.token_url = Some(token_url);
+        }
+    }
+
+    if configuration.local_port.is_none() {
+        let port = prompt(&mut rl, "Local Port: ").and_then(|s| s.parse::<u16>().ok());
+        if let Some(port) = port {
+            configuration.update_local_port(db, port)?;
+            configuration.local_port = Some(Port::from(port));
+        }
+    }
+
+    Ok(configuration)
+}
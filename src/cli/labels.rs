@@ -0,0 +1,85 @@
+use clap::Subcommand;
+
+use rust_requester::configuration::Configuration;
+use rust_requester::error::Error;
+use rust_requester::fetch;
+use rust_requester::label::Label;
+use rust_requester::process;
+use rust_requester::quota::{GmailMethod, QuotaLimiter};
+
+use crate::{auth, config, output::OutputFormat, Context};
+
+#[derive(Subcommand, Debug)]
+pub enum LabelsCommand {
+    /// Fetch labels from Gmail, save them, and print their names.
+    List {
+        /// Fetch each label individually to get message and thread counts.
+        #[arg(short, long)]
+        details: bool,
+    },
+    /// Create a label.
+    Create { name: String },
+    /// Delete a label, given its id or its name.
+    Delete { label: String },
+}
+
+pub fn run(command: LabelsCommand, config: Configuration, ctx: &Context) -> Result<(), Error> {
+    let config = config::require_complete(config, &ctx.db, ctx.non_interactive)?;
+    let profile = config.profile.clone();
+
+    let result = ctx.block_on(async {
+        let request = auth::authorized_request(config, ctx).await?;
+
+        match command {
+            LabelsCommand::List { details } => {
+                let labels = fetch::store_labels(request, details, &profile, &ctx.db).await?;
+                ctx.output.labels(&labels)
+            }
+            LabelsCommand::Create { name } => {
+                let (raw_label, _) = request.create_label(&name).await?;
+                let label: Label = serde_json::from_str(&raw_label)?;
+                let human = format!("Created label {} ({})", label.name, label.id);
+                ctx.output.emit(&label, &human)
+            }
+            LabelsCommand::Delete { label } => {
+                let (raw_list, request) = request.get_labels().await?;
+                let id = process::find_label_id(&raw_list, &label)?
+                    .ok_or_else(|| Error::NotFound(format!("no label named {}", label)))?;
+                request.delete_label(&id).await?;
+                let human = format!("Deleted label {}", id);
+                ctx.output.emit(&serde_json::json!({ "deleted": id }), &human)
+            }
+        }
+    });
+
+    report_quota(&ctx.quota, ctx.output);
+
+    result
+}
+
+/// Goes to stderr so it never gets mixed into the results on stdout.
+fn report_quota(quota: &QuotaLimiter, output: OutputFormat) {
+    let usage = quota.usage();
+    if usage.is_empty() {
+        return;
+    }
+
+    if output == OutputFormat::Json {
+        let by_method: serde_json::Map<String, serde_json::Value> = usage
+            .iter()
+            .map(|(method, units)| (method.to_string(), (*units).into()))
+            .collect();
+        let value = serde_json::json!({
+            "quota": { "units_used": quota.units_used(), "by_method": by_method }
+        });
+        eprintln!("{}", value);
+        return;
+    }
+
+    eprintln!("\nGmail quota units used: {}", quota.units_used());
+    for method in GmailMethod::ALL {
+        if let Some(units) = usage.get(&method) {
+            eprintln!("  {}: {}", method, units);
+        }
+    }
+}
@@ -1,118 +1,314 @@
-use clap::Parser;
+mod auth;
+mod config;
+mod labels;
+mod output;
 
-use rust_requester::process;
-use rust_requester::request;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
 
-use rust_requester::configuration::{port::Port, Configuration};
+use std::cell::OnceCell;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+use rust_requester::cassette;
+use rust_requester::configuration::Configuration;
 use rust_requester::db;
+use rust_requester::demo::{self, Demo};
+use rust_requester::error::Error;
+use rust_requester::event::Event;
+use rust_requester::quota::{GmailMethod, QuotaCosts, QuotaLimiter};
+use rust_requester::request::{GmailLabelRequest, Unauthorized};
+use rust_requester::token::Token;
+use rust_requester::transport::{ReqwestTransport, Transport};
+
+use output::OutputFormat;
+
+const EXIT_CODES: &str = "Exit codes:
+  0  Success
+  1  Unknown or internal error
+  2  Invalid command line arguments
+  3  Missing or invalid configuration
+  4  Database error
+  5  Network error or timeout, or an error status from the API
+  6  Authorization rejected or expired, run `auth login` again
+  7  Unexpected or unparseable API response
+  8  Local callback listener could not run, e.g. port in use
+  9  Client-side quota limiter failure
+  10  Nothing matched, e.g. no label with that name or id
+  130  Cancelled";
 
 #[derive(Parser, Debug)]
-#[command(about = include_str!("./README.md"), long_about = None)]
+#[command(
+    name = "rust_requester_cli",
+    about = include_str!("./README.md"),
+    long_about = None,
+    after_help = EXIT_CODES
+)]
 struct Args {
-    /// Reset the API configuration to blank.
-    #[arg(short, long)]
-    reset: bool,
-}
+    /// Defaults to `labels list` when omitted.
+    #[command(subcommand)]
+    command: Option<Command>,
 
-fn main() {
-    let args = Args::parse();
+    /// Override the quota unit cost of a Gmail method, e.g. labels.get=1.
+    /// May be repeated.
+    #[arg(long, global = true, value_name = "METHOD=UNITS", value_parser = parse_quota_cost)]
+    quota_cost: Vec<(GmailMethod, u32)>,
 
-    let db = db::init().unwrap();
+    /// Which set of credentials, tokens and labels to use. A new name
+    /// creates an empty profile.
+    #[arg(
+        long,
+        global = true,
+        env = "RUST_REQUESTER_PROFILE",
+        default_value = db::DEFAULT_PROFILE
+    )]
+    profile: String,
 
-    let mut config: Configuration = if args.reset {
-        Configuration::reset(&db).expect("Failed to reset configuration.")
-    } else {
-        Configuration::init(&db).expect("Failed to initialize configuration.")
-    };
+    /// How to print results and errors.
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Plain)]
+    output: OutputFormat,
 
-    if !config.is_complete() {
-        config = fill_config(config, &db).expect("Could not save new configuration.");
-    }
+    /// Log each step of the auth and fetch pipeline to stderr.
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "RUST_REQUESTER_VERBOSE",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    verbose: bool,
 
-    // We're pulling the async requester down into a blocking
-    // runtime because async isn't actually helpful for a linear
-    // CLI request like this. It's essential in the GUI version.
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap();
+    /// Write every token and API exchange to FILE, with tokens and secrets
+    /// scrubbed out.
+    #[arg(long, global = true, value_name = "FILE", env = "RUST_REQUESTER_RECORD")]
+    record: Option<PathBuf>,
 
-    let (raw_result, _) = rt.block_on(async {
-        let label_request = request::GmailLabelRequest::new(&config)
-            .await
-            .unwrap();
+    /// Answer token and API requests from a recording made with --record,
+    /// without the network. Nothing from the run is saved.
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        env = "RUST_REQUESTER_REPLAY",
+        conflicts_with = "record"
+    )]
+    replay: Option<PathBuf>,
 
-        println!("Please visit the following URL and follow the instructions to authorize this application:\n\n{}", label_request.oauth_auth_url);
+    /// Try everything out on a made up Gmail account, with no credentials
+    /// and no network. Nothing is saved.
+    #[arg(
+        long,
+        global = true,
+        env = "RUST_REQUESTER_DEMO",
+        value_parser = clap::builder::FalseyValueParser::new()
+    )]
+    demo: bool,
 
-        let (label_request, server) = label_request.clone().oauth2_token_receiver(config);
+    #[command(flatten)]
+    config: config::ConfigArgs,
+}
 
-        let code = server.await.unwrap().unwrap();
+/// What every command gets to work with, besides its own arguments.
+pub struct Context {
+    pub db: db::Connection,
+    pub quota: Arc<QuotaLimiter>,
+    pub output: OutputFormat,
+    pub non_interactive: bool,
+    pub verbose: bool,
+    /// Cancelled by Ctrl-C, and shared with every request.
+    pub cancel: CancellationToken,
+    /// Set up front when recording, replaying or in the demo. Otherwise it's
+    /// built from the configuration the first time it's needed, so a bad
+    /// proxy or CA bundle doesn't stop `config set` from fixing it.
+    transport: OnceCell<Arc<dyn Transport>>,
+    /// Replaying or in the demo, where the answers are canned and there's no
+    /// browser to send anyone to.
+    pub offline: bool,
+}
 
-        let label_request = label_request.oauth2_token_verification(code).await.unwrap();
+impl Context {
+    /// A request for `config` that spends from the shared quota, and logs
+    /// its progress in verbose mode.
+    pub fn request(&self, config: &Configuration) -> Result<GmailLabelRequest<Unauthorized>, Error> {
+        let request = GmailLabelRequest::new(config, self.transport(config)?)?
+            .with_quota(self.quota.clone())
+            .with_cancellation(self.cancel.clone());
+        if !self.verbose {
+            return Ok(request);
+        }
 
-        label_request.get_labels().await.unwrap()
-    });
+        let output = self.output;
+        Ok(request.with_observer(Arc::new(move |event: &Event| output.event(event))))
+    }
 
-    process::from_json_str(&raw_result, &db);
+    /// The one client the whole run shares, discovery and requests alike.
+    pub fn transport(&self, config: &Configuration) -> Result<Arc<dyn Transport>, Error> {
+        if let Some(transport) = self.transport.get() {
+            return Ok(transport.clone());
+        }
+        let transport = Arc::new(ReqwestTransport::from_configuration(&config.http)?);
+        Ok(self.transport.get_or_init(|| transport).clone())
+    }
 
-    // This is pulled from the database instead of directly... because I wanted to use the database.
-    let names: Vec<String> = db::get_labels(&db)
-        .iter()
-        .filter_map(|item| item.as_ref().ok())
-        .map(String::to_owned)
-        .collect();
+    /// Run `future` on a fresh runtime, with Ctrl-C cancelling the context's
+    /// token instead of killing the process. The library then stops whatever
+    /// it's waiting on and unwinds, and any open transaction rolls back as
+    /// it's dropped. Anything that isn't watching the token is dropped after
+    /// a moment instead. Whatever the future does finish with is kept, so a
+    /// real error that beats the cancellation still gets reported as itself.
+    ///
+    /// A second Ctrl-C doesn't wait for any of that.
+    pub fn block_on<T>(&self, future: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        runtime()?.block_on(async {
+            tokio::pin!(future);
+            tokio::select! {
+                result = &mut future => return result,
+                Ok(()) = tokio::signal::ctrl_c() => self.cancel.cancel(),
+            }
 
-    for name in names {
-        println!("{}", name);
+            tokio::select! {
+                result = tokio::time::timeout(CANCEL_GRACE, future) => {
+                    result.unwrap_or(Err(Error::Cancelled))
+                }
+                Ok(()) = tokio::signal::ctrl_c() => {
+                    eprintln!("Interrupted again, quitting.");
+                    std::process::exit(Error::Cancelled.exit_code());
+                }
+            }
+        })
     }
 }
 
-fn has_length(s: &String) -> bool {
-    s.len() > 0
+/// How long a cancelled run gets to wind down.
+const CANCEL_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Authorize, check or forget the stored Gmail token.
+    #[command(subcommand)]
+    Auth(auth::AuthCommand),
+    /// List, create or delete Gmail labels.
+    #[command(subcommand)]
+    Labels(labels::LabelsCommand),
+    /// Show or change the stored API configuration.
+    #[command(subcommand)]
+    Config(config::ConfigCommand),
+    /// Manage the local database.
+    #[command(subcommand)]
+    Db(DbCommand),
 }
 
-fn fill_config(
-    mut configuration: Configuration,
-    db: &db::Connection,
-) -> Result<Configuration, Box<dyn std::error::Error>> {
-    println!("Please enter missing API credentials");
-    let mut rl = rustyline::Editor::<()>::new().unwrap();
-
-    let api_id = if configuration.api.id.is_some() {
-        configuration.api.id.clone()
-    } else {
-        rl.readline("API ID: ").ok()
-    }
-    .filter(has_length);
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Clear the profile's configuration, stored token and saved labels.
+    Reset,
+}
 
-    let api_secret = if configuration.api.secret.is_some() {
-        configuration.api.secret.clone()
-    } else {
-        rl.readline("API Secret: ").ok()
+fn parse_quota_cost(s: &str) -> Result<(GmailMethod, u32), String> {
+    let (method, units) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected METHOD=UNITS, got {}", s))?;
+    let units = units.parse::<u32>().map_err(|err| err.to_string())?;
+    Ok((method.parse()?, units))
+}
+
+fn main() {
+    let args = Args::parse();
+    let output = args.output;
+
+    // Not a clap conflict, since clap would count RUST_REQUESTER_DEMO=0 as
+    // asking for the demo.
+    if args.demo && (args.record.is_some() || args.replay.is_some()) {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--demo can't be used with --record or --replay")
+            .exit();
     }
-    .filter(has_length);
 
-    let auth_url = if configuration.api.auth_url.is_some() {
-        configuration.api.auth_url.clone()
-    } else {
-        rl.readline("Auth URL: ").ok()
+    if let Err(err) = run(args) {
+        match err {
+            Error::Cancelled if output != OutputFormat::Json => {
+                eprintln!("Interrupted. Nothing half-finished was saved.")
+            }
+            _ => output.error(&err),
+        }
+        std::process::exit(err.exit_code());
     }
-    .filter(has_length);
+}
 
-    let token_url = if configuration.api.token_url.is_some() {
-        configuration.api.token_url.clone()
-    } else {
-        rl.readline("Token URL: ").ok()
+fn run(args: Args) -> Result<(), Error> {
+    // A replay works on a copy, so the redacted tokens it "receives" never
+    // replace real ones. The demo doesn't need anything that's saved at all.
+    let db = match (args.demo, &args.replay) {
+        (true, _) => db::init_in_memory()?,
+        (_, Some(_)) => db::init_scratch()?,
+        _ => db::init()?,
+    };
+    let config = match args.demo {
+        true => demo::configuration(),
+        false => args.config.apply(Configuration::init(&db, &args.profile)?)?,
+    };
+
+    let mut costs = QuotaCosts::default();
+    for (method, units) in args.quota_cost {
+        costs.set(method, units);
     }
-    .filter(has_length);
-
-    let local_port = if configuration.local_port.is_some() {
-        configuration.local_port
-    } else {
-        rl.readline("Local Port: ")
-            .ok()
-            .and_then(|s| s.parse::<u16>().map(Port::from).ok())
+
+    let ctx = Context {
+        db,
+        quota: Arc::new(QuotaLimiter::with_costs(costs)),
+        output: args.output,
+        non_interactive: args.config.non_interactive,
+        verbose: args.verbose,
+        cancel: CancellationToken::new(),
+        transport: match args.demo {
+            true => OnceCell::from(Arc::new(Demo::default()) as Arc<dyn Transport>),
+            false => {
+                let (record, replay) = (args.record.as_deref(), args.replay.as_deref());
+                match cassette::transport(record, replay, &config.http)? {
+                    Some(transport) => OnceCell::from(transport),
+                    None => OnceCell::new(),
+                }
+            }
+        },
+        offline: args.demo || args.replay.is_some(),
     };
 
-    configuration.update_config(api_id, api_secret, auth_url, token_url, local_port, &db)
+    let command = args
+        .command
+        .unwrap_or(Command::Labels(labels::LabelsCommand::List { details: false }));
+
+    // Only the commands that talk to the provider need its endpoints, so
+    // nothing else waits on discovery. Status and a local logout only look
+    // at what's stored.
+    let config = match command {
+        Command::Auth(auth::AuthCommand::Status | auth::AuthCommand::Logout { local: true }) => config,
+        Command::Auth(_) | Command::Labels(_) => config::discover(config, &args.config, &ctx)?,
+        _ => config,
+    };
+
+    match command {
+        Command::Auth(command) => auth::run(command, config, &ctx),
+        Command::Labels(command) => labels::run(command, config, &ctx),
+        Command::Config(command) => config::run(command, config, &ctx),
+        Command::Db(DbCommand::Reset) => {
+            Configuration::reset(&ctx.db, &config.profile)?;
+            Token::delete(&ctx.db, &config.profile)?;
+            db::delete_labels(&config.profile, &ctx.db)?;
+            ctx.output.emit(&serde_json::json!({ "reset": true }), "Database reset.")
+        }
+    }
+}
+
+/// We're pulling the async requester down into a blocking
+/// runtime because async isn't actually helpful for a linear
+/// CLI request like this. It's essential in the GUI version.
+fn runtime() -> Result<tokio::runtime::Runtime, Error> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    Ok(rt)
 }
@@ -0,0 +1,129 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use rust_requester::error::Error;
+use rust_requester::event::Event;
+use rust_requester::label::Label;
+
+/// How results are printed. `plain` is the original one-name-per-line
+/// output, `table` lines things up for people, `json` is for scripts.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Plain,
+    Table,
+    Json,
+}
+
+impl OutputFormat {
+    /// Print a result that has both a human and a machine form.
+    pub fn emit<T: Serialize>(self, value: &T, human: &str) -> Result<(), Error> {
+        match self {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+            OutputFormat::Plain | OutputFormat::Table => println!("{}", human),
+        }
+        Ok(())
+    }
+
+    pub fn labels(self, labels: &[Label]) -> Result<(), Error> {
+        match self {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(labels)?),
+            OutputFormat::Plain => labels.iter().for_each(|label| println!("{}", label.name)),
+            OutputFormat::Table => print_label_table(labels),
+        }
+        Ok(())
+    }
+
+    /// Verbose progress goes to stderr too, one line, or one JSON object,
+    /// per event.
+    pub fn event(self, event: &Event) {
+        match self {
+            OutputFormat::Json => match serde_json::to_string(event) {
+                Ok(json) => eprintln!("{}", json),
+                Err(err) => eprintln!("{}", err),
+            },
+            OutputFormat::Plain | OutputFormat::Table => eprintln!("> {}", event),
+        }
+    }
+
+    /// Errors go to stderr either way, so stdout only ever holds results.
+    pub fn error(self, err: &Error) {
+        let mut causes = Vec::new();
+        let mut source = std::error::Error::source(err);
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+
+        match self {
+            OutputFormat::Json => {
+                let value = serde_json::json!({
+                    "error": {
+                        "kind": err.kind(),
+                        "message": err.to_string(),
+                        "causes": causes,
+                        "exit_code": err.exit_code(),
+                    }
+                });
+                eprintln!("{}", value);
+            }
+            OutputFormat::Plain | OutputFormat::Table => {
+                eprintln!("Error: {}", err);
+                causes.iter().for_each(|cause| eprintln!("  caused by: {}", cause));
+            }
+        }
+    }
+}
+
+fn count(value: Option<isize>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_else(|| "-".to_string())
+}
+
+fn print_label_table(labels: &[Label]) {
+    let header = ["NAME", "TYPE", "MESSAGES", "UNREAD", "THREADS", "UNREAD"];
+    let rows: Vec<[String; 6]> = labels
+        .iter()
+        .map(|label| {
+            [
+                label.name.clone(),
+                label.label_type.clone(),
+                count(label.messages_total),
+                count(label.messages_unread),
+                count(label.threads_total),
+                count(label.threads_unread),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: [&str; 6]| {
+        // Names and types read left to right, counts line up on the right.
+        let line = format!(
+            "{:<w0$}  {:<w1$}  {:>w2$}  {:>w3$}  {:>w4$}  {:>w5$}",
+            cells[0],
+            cells[1],
+            cells[2],
+            cells[3],
+            cells[4],
+            cells[5],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+            w5 = widths[5],
+        );
+        println!("{}", line.trim_end());
+    };
+
+    print_row(header);
+    for row in &rows {
+        print_row([&row[0], &row[1], &row[2], &row[3], &row[4], &row[5]]);
+    }
+}
@@ -1,19 +1,44 @@
+use std::sync::Arc;
+
 use crate::resources;
 use iced::{
-    widget::{image, Column, Image, Row, Text, TextInput},
-    Element,
+    widget::{image, Button, Column, Image, PickList, Row, Text, TextInput},
+    Command, Element,
 };
 
-use rust_requester::configuration::Configuration;
+use rust_requester::configuration::{
+    client_auth::{ClientType, TokenAuthMethod},
+    discovery::{self, ProviderMetadata},
+    Configuration, HttpConfiguration,
+};
 use rust_requester::error::Error;
+use rust_requester::provider::ProviderKind;
+use rust_requester::transport::{ReqwestTransport, Transport};
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Message {
+    ProviderChanged(ProviderKind),
+    ScopesChanged(String),
+    ResourceURLChanged(String),
     ClientIDChanged(String),
     ClientSecretChanged(String),
+    ClientTypeChanged(ClientType),
+    TokenAuthMethodChanged(TokenAuthMethod),
     AuthURLChanged(String),
     TokenURLChanged(String),
+    DeviceAuthURLChanged(String),
     LocalPortChanged(String),
+    ClientSecretPathChanged(String),
+    ImportClientSecret,
+    IssuerURLChanged(String),
+    Discover,
+    Discovered(String, Result<(ProviderMetadata, String), Error>),
+    ConnectTimeoutChanged(String),
+    ReadTimeoutChanged(String),
+    UserAgentChanged(String),
+    ProxyURLChanged(String),
+    CaBundlePathChanged(String),
 }
 
 pub enum ErrorStyle {
@@ -47,6 +72,14 @@ pub struct ConfigInputs {
     pub port_raw_string: String,
     pub port_error_text: Option<String>,
     pub port_error_style: ErrorStyle,
+    pub client_secret_path: String,
+    pub import_error_text: Option<String>,
+    pub discovery_text: Option<(String, ErrorStyle)>,
+    // The timeouts are numbers too, so they get the same treatment as the
+    // port. Empty means the default.
+    pub connect_timeout_raw_string: String,
+    pub read_timeout_raw_string: String,
+    pub timeout_error_text: Option<String>,
 }
 
 impl ConfigInputs {
@@ -58,14 +91,89 @@ impl ConfigInputs {
         }
     }
 
-    pub fn new(initial_port: Option<u16>) -> Self {
+    fn parse_timeout(value: &str) -> Result<Option<u64>, String> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} is not a whole number of seconds", value))
+    }
+
+    pub fn new(initial_port: Option<u16>, http: &HttpConfiguration) -> Self {
         let initial_port_value: String = initial_port.map(|p| p.to_string()).unwrap_or_default();
         let port_error_text = initial_port.and_then(Self::check_for_low_port);
+        let seconds = |timeout: Option<u64>| timeout.map(|t| t.to_string()).unwrap_or_default();
 
         ConfigInputs {
             port_raw_string: initial_port_value,
             port_error_text,
             port_error_style: ErrorStyle::Warning,
+            client_secret_path: String::new(),
+            import_error_text: None,
+            discovery_text: None,
+            connect_timeout_raw_string: seconds(http.connect_timeout),
+            read_timeout_raw_string: seconds(http.read_timeout),
+            timeout_error_text: None,
+        }
+    }
+
+    /// Discovery is the one input that has to go out to the network, so
+    /// unlike everything in `update` it can hand back a command. A fresh
+    /// cached document is used straight away. It goes out through
+    /// `transport` if there is one, or a client built from the configuration.
+    pub fn discover(
+        &mut self,
+        config: &mut Configuration,
+        db: &rusqlite::Connection,
+        transport: Option<Arc<dyn Transport>>,
+    ) -> Command<Message> {
+        let issuer = match config.api.issuer_url.clone() {
+            Some(issuer) => issuer,
+            None => {
+                let text = "Enter an issuer URL first.".to_string();
+                self.discovery_text = Some((text, ErrorStyle::Error));
+                return Command::none();
+            }
+        };
+
+        match discovery::cached(&issuer, db) {
+            Ok(Some((metadata, true))) => {
+                self.apply_discovery(&metadata, config, db);
+                Command::none()
+            }
+            _ => {
+                let transport = match transport {
+                    Some(transport) => transport,
+                    None => match ReqwestTransport::from_configuration(&config.http) {
+                        Ok(transport) => Arc::new(transport),
+                        Err(err) => {
+                            self.discovery_text = Some((err.to_string(), ErrorStyle::Error));
+                            return Command::none();
+                        }
+                    },
+                };
+                self.discovery_text = Some(("Discovering...".to_string(), ErrorStyle::Warning));
+                Command::perform(discovery::fetch(issuer.clone(), transport), move |result| {
+                    Message::Discovered(issuer.clone(), result)
+                })
+            }
+        }
+    }
+
+    fn apply_discovery(
+        &mut self,
+        metadata: &ProviderMetadata,
+        config: &mut Configuration,
+        db: &rusqlite::Connection,
+    ) {
+        match config.import_discovery(metadata, db) {
+            Ok(imported) => {
+                *config = imported;
+                self.discovery_text = None;
+            }
+            Err(err) => self.discovery_text = Some((err.to_string(), ErrorStyle::Error)),
         }
     }
 
@@ -76,6 +184,21 @@ impl ConfigInputs {
         db: &rusqlite::Connection,
     ) -> Result<Configuration, Error> {
         match incoming_message {
+            Message::ProviderChanged(value) => {
+                config.update_provider(db, value)?;
+                config.api.provider = value;
+                Ok(config.clone())
+            }
+            Message::ScopesChanged(value) => {
+                config.update_scopes(db, value.clone())?;
+                config.api.scopes = Some(value).filter(|scopes| !scopes.is_empty());
+                Ok(config.clone())
+            }
+            Message::ResourceURLChanged(value) => {
+                config.update_resource_url(db, value.clone())?;
+                config.api.resource_url = Some(value).filter(|url| !url.is_empty());
+                Ok(config.clone())
+            }
             Message::ClientIDChanged(value) => {
                 config.update_id(db, value.clone())?;
                 config.api.id = Some(value);
@@ -86,6 +209,16 @@ impl ConfigInputs {
                 config.api.secret = Some(value);
                 Ok(config.clone())
             }
+            Message::ClientTypeChanged(value) => {
+                config.update_client_type(db, value)?;
+                config.api.client_type = value;
+                Ok(config.clone())
+            }
+            Message::TokenAuthMethodChanged(value) => {
+                config.update_token_auth_method(db, value)?;
+                config.api.token_auth_method = value;
+                Ok(config.clone())
+            }
             Message::AuthURLChanged(value) => {
                 config.update_auth_url(db, value.clone())?;
                 config.api.auth_url = Some(value);
@@ -96,6 +229,11 @@ impl ConfigInputs {
                 config.api.token_url = Some(value);
                 Ok(config.clone())
             }
+            Message::DeviceAuthURLChanged(value) => {
+                config.update_device_auth_url(db, value.clone())?;
+                config.api.device_auth_url = Some(value).filter(|url| !url.is_empty());
+                Ok(config.clone())
+            }
             Message::LocalPortChanged(value) => {
                 let port = value.parse::<u16>();
                 match port {
@@ -119,12 +257,175 @@ impl ConfigInputs {
                     }
                 }
             }
+            Message::IssuerURLChanged(value) => {
+                config.update_issuer_url(db, value.clone())?;
+                config.api.issuer_url = Some(value).filter(|url| !url.is_empty());
+                Ok(config.clone())
+            }
+            // Handed off to `discover` by the app, since it needs a command.
+            Message::Discover => Ok(config.clone()),
+            Message::Discovered(issuer, result) => {
+                let stored = result.and_then(|(metadata, raw)| {
+                    discovery::store(&issuer, &raw, db)?;
+                    Ok(metadata)
+                });
+                match stored {
+                    Ok(metadata) => self.apply_discovery(&metadata, config, db),
+                    Err(err) => self.discovery_text = Some((err.to_string(), ErrorStyle::Error)),
+                }
+                Ok(config.clone())
+            }
+            Message::ConnectTimeoutChanged(value) => {
+                let parsed = Self::parse_timeout(&value);
+                self.connect_timeout_raw_string = value;
+                match parsed {
+                    Ok(seconds) => {
+                        self.timeout_error_text = None;
+                        config.update_connect_timeout(db, seconds)?;
+                        config.http.connect_timeout = seconds;
+                    }
+                    Err(err) => self.timeout_error_text = Some(err),
+                }
+                Ok(config.clone())
+            }
+            Message::ReadTimeoutChanged(value) => {
+                let parsed = Self::parse_timeout(&value);
+                self.read_timeout_raw_string = value;
+                match parsed {
+                    Ok(seconds) => {
+                        self.timeout_error_text = None;
+                        config.update_read_timeout(db, seconds)?;
+                        config.http.read_timeout = seconds;
+                    }
+                    Err(err) => self.timeout_error_text = Some(err),
+                }
+                Ok(config.clone())
+            }
+            Message::UserAgentChanged(value) => {
+                config.update_user_agent(db, value.clone())?;
+                config.http.user_agent = Some(value).filter(|agent| !agent.is_empty());
+                Ok(config.clone())
+            }
+            Message::ProxyURLChanged(value) => {
+                config.update_proxy_url(db, value.clone())?;
+                config.http.proxy_url = Some(value).filter(|url| !url.is_empty());
+                Ok(config.clone())
+            }
+            Message::CaBundlePathChanged(value) => {
+                config.update_ca_bundle_path(db, value.clone())?;
+                config.http.ca_bundle_path = Some(value).filter(|path| !path.is_empty());
+                Ok(config.clone())
+            }
+            Message::ClientSecretPathChanged(value) => {
+                self.client_secret_path = value;
+                Ok(config.clone())
+            }
+            Message::ImportClientSecret => {
+                // A bad path is an everyday typo, not a reason to bring the
+                // whole app down, so it's shown inline instead of returned.
+                match config.import_client_secret(&self.client_secret_path, db) {
+                    Ok(imported) => {
+                        self.import_error_text = None;
+                        if let Some(port) = imported.local_port {
+                            self.port_raw_string = port.to_string();
+                            self.port_error_text = Self::check_for_low_port(port.as_u16());
+                        }
+                        Ok(imported)
+                    }
+                    Err(err) => {
+                        self.import_error_text = Some(err.to_string());
+                        Ok(config.clone())
+                    }
+                }
+            }
         }
     }
 
-    pub fn view(&self, config: &Configuration) -> Element<Message> {
+    pub fn view(&self, config: &Configuration) -> Element<'_, Message> {
         let column = Column::new().max_width(500).spacing(10);
 
+        let import_row = Row::new()
+            .spacing(10)
+            .push(TextInput::new(
+                "PATH TO client_secret.json",
+                &self.client_secret_path,
+                Message::ClientSecretPathChanged,
+            ))
+            .push(Button::new(Text::new("Import")).on_press(Message::ImportClientSecret));
+
+        let column = column
+            .push(Text::new("Import from Google client_secret.json:"))
+            .push(import_row);
+
+        let column = if let Some(error_text) = &self.import_error_text {
+            let style = ErrorStyle::Error;
+            column.push(
+                Row::new()
+                    .spacing(10)
+                    .push(style.image())
+                    .push(Text::new(error_text).style(style.color())),
+            )
+        } else {
+            column
+        };
+
+        let discover_row = Row::new()
+            .spacing(10)
+            .push(TextInput::new(
+                "ISSUER URL",
+                config.api.issuer_url.as_deref().unwrap_or(""),
+                Message::IssuerURLChanged,
+            ))
+            .push(Button::new(Text::new("Discover")).on_press(Message::Discover));
+
+        let column = column
+            .push(Text::new("Or discover the endpoints from an OpenID Connect issuer:"))
+            .push(discover_row);
+
+        let column = if let Some((text, style)) = &self.discovery_text {
+            column.push(
+                Row::new()
+                    .spacing(10)
+                    .push(style.image())
+                    .push(Text::new(text).style(style.color())),
+            )
+        } else {
+            column
+        };
+
+        let column = column.push(
+            Row::new()
+                .spacing(10)
+                .push(Text::new("Provider:"))
+                .push(PickList::new(
+                    &ProviderKind::ALL[..],
+                    Some(config.api.provider),
+                    Message::ProviderChanged,
+                )),
+        );
+
+        let custom = config.api.provider == ProviderKind::Custom;
+        let column = if custom {
+            column
+                .push(Text::new("Scopes (space separated):"))
+                .push(TextInput::new(
+                    "SCOPES",
+                    config.api.scopes.as_deref().unwrap_or(""),
+                    Message::ScopesChanged,
+                ))
+                .push(Text::new("Resource URL:"))
+                .push(TextInput::new(
+                    "RESOURCE URL",
+                    config.api.resource_url.as_deref().unwrap_or(""),
+                    Message::ResourceURLChanged,
+                ))
+        } else {
+            column
+        };
+
+        // Google's endpoints are known, so its URL fields are only overrides.
+        let optional = if custom { "" } else { " (optional)" };
+
         let column = column
             .push(Text::new("Client ID:"))
             .push(TextInput::new(
@@ -132,27 +433,63 @@ impl ConfigInputs {
                 config.api.id.as_deref().unwrap_or(""),
                 Message::ClientIDChanged,
             ))
-            .push(Text::new("Client Secret:"))
             .push(
-                TextInput::new(
-                    "CLIENT SECRET",
-                    config.api.secret.as_deref().unwrap_or(""),
-                    Message::ClientSecretChanged,
+                Row::new()
+                    .spacing(10)
+                    .push(Text::new("Client Type:"))
+                    .push(PickList::new(
+                        &ClientType::ALL[..],
+                        Some(config.api.client_type),
+                        Message::ClientTypeChanged,
+                    )),
+            );
+
+        // Public clients have nothing to send, so the secret and how to
+        // send it are only worth showing for confidential ones.
+        let column = if config.api.client_type == ClientType::Confidential {
+            column
+                .push(Text::new("Client Secret:"))
+                .push(
+                    TextInput::new(
+                        "CLIENT SECRET",
+                        config.api.secret.as_deref().unwrap_or(""),
+                        Message::ClientSecretChanged,
+                    )
+                    .password(),
                 )
-                .password(),
-            )
-            .push(Text::new("Auth URL:"))
+                .push(
+                    Row::new()
+                        .spacing(10)
+                        .push(Text::new("Token Endpoint Auth:"))
+                        .push(PickList::new(
+                            &TokenAuthMethod::ALL[..],
+                            Some(config.api.token_auth_method),
+                            Message::TokenAuthMethodChanged,
+                        )),
+                )
+        } else {
+            column
+        };
+
+        let column = column
+            .push(Text::new(format!("Auth URL{}:", optional)))
             .push(TextInput::new(
                 "AUTH URL",
                 config.api.auth_url.as_deref().unwrap_or(""),
                 Message::AuthURLChanged,
             ))
-            .push(Text::new("Token URL:"))
+            .push(Text::new(format!("Token URL{}:", optional)))
             .push(TextInput::new(
                 "TOKEN URL",
                 config.api.token_url.as_deref().unwrap_or(""),
                 Message::TokenURLChanged,
             ))
+            .push(Text::new("Device Auth URL (optional, for device code sign in):"))
+            .push(TextInput::new(
+                "DEVICE AUTH URL",
+                config.api.device_auth_url.as_deref().unwrap_or(""),
+                Message::DeviceAuthURLChanged,
+            ))
             .push(Text::new("Local Port:"))
             .push(TextInput::new(
                 "LOCAL PORT",
@@ -174,6 +511,53 @@ impl ConfigInputs {
             column
         };
 
+        // Empty boxes use the defaults, so they're all optional.
+        let column = column
+            .push(Text::new("Connect Timeout, seconds (optional):"))
+            .push(TextInput::new(
+                "CONNECT TIMEOUT",
+                &self.connect_timeout_raw_string,
+                Message::ConnectTimeoutChanged,
+            ))
+            .push(Text::new("Read Timeout, seconds (optional):"))
+            .push(TextInput::new(
+                "READ TIMEOUT",
+                &self.read_timeout_raw_string,
+                Message::ReadTimeoutChanged,
+            ));
+
+        let column = if let Some(error_text) = &self.timeout_error_text {
+            let style = ErrorStyle::Error;
+            column.push(
+                Row::new()
+                    .spacing(10)
+                    .push(style.image())
+                    .push(Text::new(error_text).style(style.color())),
+            )
+        } else {
+            column
+        };
+
+        let column = column
+            .push(Text::new("User Agent (optional):"))
+            .push(TextInput::new(
+                "USER AGENT",
+                config.http.user_agent.as_deref().unwrap_or(""),
+                Message::UserAgentChanged,
+            ))
+            .push(Text::new("Proxy URL (optional):"))
+            .push(TextInput::new(
+                "PROXY URL",
+                config.http.proxy_url.as_deref().unwrap_or(""),
+                Message::ProxyURLChanged,
+            ))
+            .push(Text::new("CA Bundle Path (optional, PEM):"))
+            .push(TextInput::new(
+                "CA BUNDLE PATH",
+                config.http.ca_bundle_path.as_deref().unwrap_or(""),
+                Message::CaBundlePathChanged,
+            ));
+
         column.into()
     }
 }
@@ -1,34 +1,201 @@
 use iced::{
-    widget::{Button, Column, Text},
+    widget::{Button, Column, Row, Text},
     Command, Element,
 };
-use rust_requester::{configuration::Configuration, error::Error, request::GmailLabelRequest};
-use webbrowser;
+use std::sync::Arc;
+
+use jsonwebtoken::jwk::JwkSet;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+use rust_requester::{
+    configuration::Configuration,
+    error::Error,
+    event::Event,
+    fetch, id_token, provider,
+    request::{AwaitingDevice, GmailLabelRequest, Unauthorized},
+    token::{Status, Token},
+    transport::{ReqwestTransport, Transport},
+};
 
 #[derive(Debug, Clone)]
 pub enum Message {
     MakeLabelRequest,
     MakeOauthRequest,
+    MakeDeviceRequest,
     Cancel,
-    ReceivedOauthTargetURL(Result<GmailLabelRequest, String>),
-    ReceivedOauthCode(Result<String, Error>),
+    SignOut,
+    SignedOut(Result<(), Error>),
+    ReceivedDeviceCode(Result<GmailLabelRequest<AwaitingDevice>, Error>),
     ReceivedOauthToken(Result<GmailLabelRequest, Error>),
+    ReceivedKeySet(String, Result<(JwkSet, String), Error>),
     LabelsReceived(Result<(String, GmailLabelRequest), Error>),
+    Progress(Event),
 }
 
 pub struct LabelRequestPanel {
     request: Option<GmailLabelRequest>,
     display_errors: Option<String>,
     labels: Option<Vec<String>>,
+    pending: Option<Pending>,
+    account: Option<String>,
+    // What's stored for the profile, shown next to the authorize button.
+    status: Status,
+    // Requests report to this, and what they report comes back as Progress.
+    observer: UnboundedSender<Event>,
+    progress: Vec<Event>,
+    // Shared by every request made since the last Cancel.
+    cancel: CancellationToken,
+    transport: Option<Arc<dyn Transport>>,
+    // There's no browser to wait for when the answers are canned.
+    offline: bool,
+}
+
+/// A flow that's waiting on the user: in the browser, or on another device,
+/// with the verification URL and code. Or just on the provider, while the
+/// stored token is refreshed.
+enum Pending {
+    Browser,
+    Device(String, String),
+    Refresh,
 }
 
 impl LabelRequestPanel {
-    pub fn new() -> Self {
+    pub fn new(
+        observer: UnboundedSender<Event>,
+        transport: Option<Arc<dyn Transport>>,
+        offline: bool,
+    ) -> Self {
         LabelRequestPanel {
             request: None,
             display_errors: None,
             labels: None,
+            pending: None,
+            account: None,
+            status: Status::default(),
+            observer,
+            progress: Vec::new(),
+            cancel: CancellationToken::new(),
+            transport,
+            offline,
+        }
+    }
+
+    fn new_request(&self, config: &Configuration) -> Result<GmailLabelRequest<Unauthorized>, Error> {
+        let transport: Arc<dyn Transport> = match &self.transport {
+            Some(transport) => transport.clone(),
+            None => Arc::new(ReqwestTransport::from_configuration(&config.http)?),
+        };
+        Ok(GmailLabelRequest::new(config, transport)?
+            .with_observer(Arc::new(self.observer.clone()))
+            .with_cancellation(self.cancel.clone()))
+    }
+
+    /// Reread the stored token. Cheap enough to do after every message, which
+    /// keeps it honest through logins, refreshes, sign outs and scope edits.
+    pub fn refresh_status(&mut self, config: &Configuration, db: &rusqlite::Connection) {
+        let provider = provider::from_configuration(config);
+        match Token::load(db, &config.profile) {
+            Ok(token) => self.status = Status::new(token.as_ref(), &*provider),
+            Err(err) => self.display_errors = Some(err.to_string()),
+        }
+    }
+
+    /// The authorized account, once its ID token has checked out.
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    /// Check the ID token before going any further. The keys usually come
+    /// from the cache, but a new or rotated key set has to be fetched first.
+    fn check_identity(
+        &mut self,
+        request: GmailLabelRequest,
+        config: &Configuration,
+        db: &rusqlite::Connection,
+    ) -> Command<Message> {
+        let raw = request.token().id_token.clone();
+        let (raw, url) = match (raw, request.jwks_url()) {
+            (Some(raw), Some(url)) => (raw, url.to_string()),
+            _ => return self.authorized(request, config, db),
+        };
+
+        let cached = id_token::cached(&url, db).ok().flatten();
+        if let Some((keys, true)) = cached {
+            if let Ok(false) = id_token::needs_refresh(&raw, &keys) {
+                return match request.verify_identity(&keys) {
+                    Ok(request) => self.authorized(request, config, db),
+                    Err(err) => {
+                        self.display_errors = Some(err.to_string());
+                        Command::none()
+                    }
+                };
+            }
         }
+
+        let transport = request.transport();
+        self.request = Some(request);
+        Command::perform(id_token::fetch(url.clone(), transport), move |result| {
+            Message::ReceivedKeySet(url.clone(), result)
+        })
+    }
+
+    /// Save the token, so the labels can be attributed to its account, then
+    /// fetch them.
+    fn authorized(
+        &mut self,
+        request: GmailLabelRequest,
+        config: &Configuration,
+        db: &rusqlite::Connection,
+    ) -> Command<Message> {
+        let token = request.token();
+        if let Err(err) = token.save(db, &config.profile) {
+            self.display_errors = Some(err.to_string());
+        }
+        self.account = token.email.clone();
+
+        let cloned_request = request.clone();
+        self.request = Some(request);
+        Command::perform(cloned_request.get_labels(), Message::LabelsReceived)
+    }
+
+    /// Use the stored token, refreshed if it has to be, and only send the
+    /// user to the browser when there's nothing usable. Same as the CLI, by
+    /// way of `fetch::authorize`.
+    fn sign_in(&mut self, config: &Configuration, db: &rusqlite::Connection) -> Command<Message> {
+        let started = self
+            .new_request(config)
+            .and_then(|request| Ok((request, Token::load(db, &config.profile)?)));
+        let (request, stored) = match started {
+            Ok(started) => started,
+            Err(err) => {
+                self.display_errors = Some(err.to_string());
+                return Command::none();
+            }
+        };
+        self.progress.clear();
+
+        // Which of them `authorize` is about to do, for the Cancel button.
+        self.pending = Some(match &stored {
+            Some(token) if !token.is_expired() || token.refresh_token.is_some() => Pending::Refresh,
+            _ => Pending::Browser,
+        });
+
+        // The URL is shown as progress, so it can still be copied across by
+        // hand if no browser opens.
+        let offline = self.offline;
+        let login = move |request: GmailLabelRequest<Unauthorized>| async move {
+            if offline {
+                let code = String::from("REDACTED");
+                return request.authorize().oauth2_token_verification(code).await;
+            }
+            fetch::browser_login(request, |url| {
+                if let Err(err) = webbrowser::open(url.as_str()) {
+                    eprintln!("Could not open default browser: {}", err);
+                }
+            })
+            .await
+        };
+        Command::perform(fetch::authorize(request, stored, login), Message::ReceivedOauthToken)
     }
 
     pub fn update(
@@ -37,13 +204,31 @@ impl LabelRequestPanel {
         db: &rusqlite::Connection,
         incoming_message: Message,
     ) -> Command<Message> {
+        // Progress only adds to the view, so whatever is shown stays put.
+        if let Message::Progress(event) = incoming_message {
+            // A refresh that was turned down ends up in the browser after all.
+            if let (Event::UrlReady { .. }, Some(Pending::Refresh)) = (&event, &self.pending) {
+                self.pending = Some(Pending::Browser);
+            }
+            self.progress.push(event);
+            return Command::none();
+        }
+
         self.display_errors = None;
         match incoming_message {
             Message::MakeLabelRequest => {
                 let request = self.request.clone();
                 match request {
+                    // Held since before it expired. The stored copy is the
+                    // same token, so signing in again refreshes it.
+                    Some(request) if request.token().is_expired() => {
+                        self.labels = None;
+                        self.request = None;
+                        return self.sign_in(config, db);
+                    }
                     Some(request) => {
                         self.labels = None;
+                        self.progress.clear();
                         let cloned_request = request.clone();
                         self.request = Some(request);
                         return Command::perform(
@@ -57,121 +242,164 @@ impl LabelRequestPanel {
                     }
                 }
             }
-            Message::MakeOauthRequest => {
-                let config = config.clone();
+            Message::MakeOauthRequest => return self.sign_in(config, db),
+            Message::MakeDeviceRequest => {
+                self.progress.clear();
+                let request = self.new_request(config);
                 return Command::perform(
-                    async move {
-                        GmailLabelRequest::new(&config).await.map_err(|err| {
-                            eprintln!("{}", err);
-                            err.to_string()
-                        })
-                    },
-                    Message::ReceivedOauthTargetURL,
+                    async move { request?.oauth2_device_authorization().await },
+                    Message::ReceivedDeviceCode,
                 );
             }
-            Message::ReceivedOauthTargetURL(res) => {
-                let config = config.clone();
-                match res {
-                    Ok(request) => {
-                        let try_browser = webbrowser::open(request.oauth_auth_url.as_str());
-                        if let Err(err) = try_browser {
-                            eprintln!("{}", err);
-                            self.display_errors =
-                                Some("Could not open default browser.".to_owned());
-                        }
-                        let (request, server) = request.oauth2_token_receiver(config);
-                        self.request = Some(request);
-                        return Command::perform(async move {
-                                match server.await {
-                                    Ok(result) => result,
-                                    Err(err) => Err(Error::from(Box::from(err)))
-                                }
-                            },
-                            Message::ReceivedOauthCode,
-                        );
-                    }
-                    Err(err) => {
-                        self.display_errors = Some(err);
-                    }
-                }
-            },
-            Message::ReceivedOauthCode(maybe_code) => {
-                match maybe_code {
-                    Ok(code) => {
-                        let maybe_request = self.request.clone();
-                        match maybe_request {
-                            Some(request) => {
-                                return Command::perform(
-                                    request.oauth2_token_verification(code),
-                                    Message::ReceivedOauthToken
-                                );                                
-                            },
-                            None => {
-                                self.display_errors = Some("Request canceled, please retry.".to_string());
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        self.display_errors = Some(err.to_string());
-                    }
-                }
-            },
-            Message::ReceivedOauthToken(request) => match request {
+            Message::Progress(_) => {}
+            // Whatever was cancelled has already been cleared away.
+            Message::ReceivedDeviceCode(Err(Error::Cancelled))
+            | Message::ReceivedOauthToken(Err(Error::Cancelled))
+            | Message::LabelsReceived(Err(Error::Cancelled)) => {}
+            Message::ReceivedDeviceCode(res) => match res {
                 Ok(request) => {
-                    let cloned_request = request.clone();
-                    self.request = Some(request);
-                    return Command::perform(cloned_request.get_labels(), Message::LabelsReceived);
+                    let details = request.details();
+                    self.pending = Some(Pending::Device(
+                        details.verification_uri().to_string(),
+                        details.user_code().secret().clone(),
+                    ));
+                    return Command::perform(
+                        request.oauth2_device_token(),
+                        Message::ReceivedOauthToken,
+                    );
                 }
                 Err(err) => {
                     self.display_errors = Some(err.to_string());
                 }
             },
+            // Cancelling stops the flow, but a token that was already on its
+            // way back when Cancel was pressed still arrives. Nobody's waiting
+            // for it any more, so it's dropped.
+            Message::ReceivedOauthToken(_) if self.pending.is_none() => {}
+            Message::ReceivedOauthToken(request) => {
+                self.pending = None;
+                match request {
+                    Ok(request) => return self.check_identity(request, config, db),
+                    Err(err) => self.display_errors = Some(err.to_string()),
+                }
+            }
+            Message::ReceivedKeySet(_, _) if self.request.is_none() => {}
+            Message::ReceivedKeySet(url, result) => {
+                let request = self.request.take();
+                let verified = result
+                    .and_then(|(keys, raw)| {
+                        id_token::store(&url, &raw, db)?;
+                        Ok(keys)
+                    })
+                    .and_then(|keys| request.ok_or(Error::MissingToken)?.verify_identity(&keys));
+                match verified {
+                    Ok(request) => return self.authorized(request, config, db),
+                    Err(err) => self.display_errors = Some(err.to_string()),
+                }
+            }
             Message::LabelsReceived(res) => match res {
                 Ok((json, request)) => {
+                    let saved = fetch::save_labels(&json, &config.profile, db, request.events());
                     self.request = Some(request);
-                    rust_requester::process::from_json_str(&json, db);
-                    self.labels = Some(
-                        rust_requester::db::get_labels(&db)
-                            .iter()
-                            .filter_map(|item| item.as_ref().ok())
-                            .map(String::to_owned)
-                            .collect(),
-                    );
+                    match saved {
+                        Ok(labels) => {
+                            self.labels = Some(labels.into_iter().map(|label| label.name).collect())
+                        }
+                        Err(err) => self.display_errors = Some(err.to_string()),
+                    }
                 }
                 Err(err) => {
                     self.display_errors = Some(err.to_string());
                 }
             },
+            Message::SignOut => {
+                self.labels = None;
+                self.progress.clear();
+                self.account = None;
+                self.pending = None;
+                self.display_errors = None;
+                if let Some(request) = self.request.take() {
+                    if request.can_revoke() {
+                        return Command::perform(request.revoke(), Message::SignedOut);
+                    }
+                }
+                return Command::perform(async { Ok(()) }, Message::SignedOut);
+            }
+            // The stored token goes either way, the provider just may not
+            // have heard about it.
+            Message::SignedOut(result) => {
+                if let Err(err) = result {
+                    self.display_errors = Some(format!("Could not revoke the token: {}", err));
+                }
+                if let Err(err) = Token::delete(db, &config.profile) {
+                    self.display_errors = Some(err.to_string());
+                }
+            }
             Message::Cancel => {
                 self.display_errors = None;
-                let mut request = self.request.take();
-                request.as_mut().map(GmailLabelRequest::kill);
+                self.progress.clear();
+                self.account = None;
+                self.request = None;
+                self.pending = None;
+                self.cancel.cancel();
+                self.cancel = CancellationToken::new();
             }
         }
         Command::none()
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
         let column = Column::new();
 
         let request_button = if self.display_errors.is_none() {
-            match self.request.as_ref() {
-                Some(req) => {
-                    if req.token.is_some() {
-                        Button::new(Text::new("Get/Refresh Labels"))
-                            .on_press(Message::MakeLabelRequest)
-                    } else {
-                        Button::new(Text::new("Cancel (awaiting browser authorization)"))
-                            .on_press(Message::Cancel)
-                    }
+            match (&self.pending, &self.request) {
+                (Some(Pending::Refresh), _) => {
+                    Button::new(Text::new("Cancel (refreshing token)")).on_press(Message::Cancel)
+                }
+                (Some(_), _) => Button::new(Text::new("Cancel (awaiting browser authorization)"))
+                    .on_press(Message::Cancel),
+                (None, Some(_)) => Button::new(Text::new("Get/Refresh Labels"))
+                    .on_press(Message::MakeLabelRequest),
+                (None, None) => {
+                    Button::new(Text::new("Authorize App")).on_press(Message::MakeOauthRequest)
                 }
-                None => Button::new(Text::new("Authorize App")).on_press(Message::MakeOauthRequest),
             }
         } else {
             Button::new(Text::new("Reset Request")).on_press(Message::Cancel)
         };
 
-        let column = column.push(request_button);
+        let column = column.push(
+            Row::new()
+                .spacing(20)
+                .push(request_button)
+                .push(Text::new(status_text(&self.status))),
+        );
+
+        let column = if self.request.is_some() {
+            column.push(Button::new(Text::new("Sign out")).on_press(Message::SignOut))
+        } else {
+            column
+        };
+
+        let column = if self.request.is_none() && self.pending.is_none() && self.display_errors.is_none() {
+            column.push(
+                Button::new(Text::new("Authorize With a Device Code"))
+                    .on_press(Message::MakeDeviceRequest),
+            )
+        } else {
+            column
+        };
+
+        let column = if let Some(Pending::Device(uri, code)) = &self.pending {
+            column.push(Text::new(format!("On any device, visit {} and enter the code {}", uri, code)))
+        } else {
+            column
+        };
+
+        let column = self
+            .progress
+            .iter()
+            .fold(column, |acc, event| acc.push(Text::new(format!("✓ {}", event))));
 
         let column = if let Some(err) = self.display_errors.clone() {
             column.push(Text::new(err))
@@ -190,3 +418,38 @@ impl LabelRequestPanel {
         column.into()
     }
 }
+
+fn status_text(status: &Status) -> String {
+    if !status.authorized {
+        return "No token stored".to_string();
+    }
+
+    let expiry = match status.expires_in_seconds {
+        Some(_) if status.expired && status.has_refresh_token => {
+            "expired, refreshes on the next fetch".to_string()
+        }
+        Some(_) if status.expired => "expired".to_string(),
+        Some(seconds) => format!("expires in {} min", seconds / 60),
+        None => "no stated expiry".to_string(),
+    };
+    let refreshed = match status.seconds_since_refresh() {
+        Some(seconds) => format!("refreshed {} min ago", seconds / 60),
+        None => "last refresh unknown".to_string(),
+    };
+    let scopes = if status.granted_scopes.is_empty() {
+        "scopes as requested".to_string()
+    } else {
+        format!("scopes granted: {}", status.granted_scopes.join(" "))
+    };
+
+    let mut text = format!("Token stored: {}, {}, {}", expiry, refreshed, scopes);
+    if !status.missing_scopes.is_empty() {
+        text.push_str(&format!(
+            "\nNot granted: {}. Authorize again to consent.",
+            status.missing_scopes.join(" ")
+        ));
+    } else if status.needs_consent {
+        text.push_str("\nNo refresh token, authorize again.");
+    }
+    text
+}
@@ -2,14 +2,24 @@ mod config_inputs;
 mod label_request_panel;
 mod resources;
 
-use config_inputs::ConfigInputs;
+use config_inputs::{ConfigInputs, ErrorStyle};
 use label_request_panel::LabelRequestPanel;
+use rust_requester::cassette;
 use rust_requester::configuration::{port::Port, Configuration};
 use rust_requester::db;
+use rust_requester::demo::{self, Demo};
+use rust_requester::error::Error;
+use rust_requester::event::Event;
+use rust_requester::transport::Transport;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
 
 use iced::executor;
-use iced::widget::{Column, Text};
-use iced::{Application, Command, Element, Settings, Subscription, Theme};
+use iced::widget::{Button, Column, PickList, Row, Text, TextInput};
+use iced::{subscription, Application, Command, Element, Settings, Subscription, Theme};
 
 // Iced was chosen here instead of other options because it espouses the
 // inimitable Elm Architecture. I'm still sad about how the architect of
@@ -21,7 +31,11 @@ use iced::{Application, Command, Element, Settings, Subscription, Theme};
 // that the caller doesn't have need to access whereever possible. 
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant, clippy::enum_variant_names)]
 enum Message {
+    ProfileSelected(String),
+    NewProfileNameChanged(String),
+    CreateProfile,
     ConfigMessage(config_inputs::Message),
     LabelRequestPanelMessage(label_request_panel::Message),
 }
@@ -29,33 +43,126 @@ enum Message {
 pub fn main() -> iced::Result {
     RustRequester::run(Settings {
         antialiasing: true,
+        flags: Flags::from_env(),
         ..Settings::default()
     })
 }
 
+/// The CLI's `--record`, `--replay` and `--demo`. There's no parser for the
+/// GUI's command line, so `--demo` is the only argument it knows, and the
+/// rest only come from the environment.
+#[derive(Debug, Default)]
+struct Flags {
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    demo: bool,
+}
+
+impl Flags {
+    fn from_env() -> Self {
+        Self {
+            record: std::env::var_os("RUST_REQUESTER_RECORD").map(PathBuf::from),
+            replay: std::env::var_os("RUST_REQUESTER_REPLAY").map(PathBuf::from),
+            demo: std::env::args().any(|arg| arg == "--demo") || env_flag("RUST_REQUESTER_DEMO"),
+        }
+    }
+}
+
+/// Whether a flag is switched on in the environment, read the way the CLI's
+/// `FalseyValueParser` reads it: unset, empty, `0`, `n`, `no`, `f`, `false`
+/// and `off` are off, and anything else is on.
+fn env_flag(name: &str) -> bool {
+    match std::env::var(name) {
+        Ok(value) => !matches!(
+            value.to_ascii_lowercase().as_str(),
+            "" | "0" | "n" | "no" | "f" | "false" | "off"
+        ),
+        Err(_) => false,
+    }
+}
+
+/// For starting up, when there's no window yet to show an error in.
+fn or_exit<T>(result: Result<T, Error>, doing: &str) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Could not {}: {}", doing, err);
+        std::process::exit(err.exit_code());
+    })
+}
+
 struct RustRequester {
+    profiles: Vec<String>,
+    new_profile_name: String,
+    // A profile that couldn't be loaded, created or saved. The previous one
+    // stays on screen.
+    profile_error: Option<String>,
     config: Configuration,
     config_inputs: ConfigInputs,
     db: rusqlite::Connection,
     label_request_panel: LabelRequestPanel,
+    // Events from every request the panel makes, for the subscription to
+    // pass back in. The receiver has to be shared, since subscription() only
+    // gets &self.
+    event_sender: UnboundedSender<Event>,
+    event_receiver: Arc<Mutex<UnboundedReceiver<Event>>>,
+    // For recording, replaying or the demo, handed to every new panel.
+    transport: Option<Arc<dyn Transport>>,
+    offline: bool,
+    // The demo has one made up profile, and nothing to configure.
+    demo: bool,
 }
 
 impl Application for RustRequester {
     type Executor = executor::Default;
     type Message = Message;
     type Theme = Theme;
-    type Flags = ();
+    type Flags = Flags;
 
-    fn new(_flags: ()) -> (RustRequester, Command<Self::Message>) {
-        let db = db::init().unwrap();
-        let config = Configuration::init(&db).unwrap();
-        let initial_port = config.local_port.map(Port::as_u16);
+    fn new(flags: Flags) -> (RustRequester, Command<Self::Message>) {
+        // Like the CLI, a replay works on a copy of the database, and the
+        // demo on an empty one.
+        let offline = flags.demo || flags.replay.is_some();
+        let (db, config, transport): (_, _, Option<Arc<dyn Transport>>) = if flags.demo {
+            let db = or_exit(db::init_in_memory(), "open the database");
+            or_exit(db::create_profile(demo::PROFILE, &db), "create the demo profile");
+            (db, demo::configuration(), Some(Arc::new(Demo::default())))
+        } else {
+            let db = or_exit(
+                if offline { db::init_scratch() } else { db::init() },
+                "open the database",
+            );
+            let config = or_exit(
+                Configuration::init(&db, db::DEFAULT_PROFILE),
+                "load the default profile",
+            );
+            let (record, replay) = (flags.record.as_deref(), flags.replay.as_deref());
+            // Carrying on without would quietly skip the recording or replay
+            // that was asked for, so this is the end of the run too.
+            let transport = or_exit(
+                cassette::transport(record, replay, &config.http),
+                "set up the cassette",
+            );
+            (db, config, transport)
+        };
+        let profiles = or_exit(db::list_profiles(&db), "list the profiles");
+        let config_inputs = ConfigInputs::new(config.local_port.map(Port::as_u16), &config.http);
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let mut label_request_panel =
+            LabelRequestPanel::new(event_sender.clone(), transport.clone(), offline);
+        label_request_panel.refresh_status(&config, &db);
         (
             RustRequester {
+                profiles,
+                new_profile_name: String::new(),
+                profile_error: None,
                 config,
-                config_inputs: ConfigInputs::new(initial_port),
+                config_inputs,
                 db,
-                label_request_panel: LabelRequestPanel::new(),
+                label_request_panel,
+                event_sender,
+                event_receiver: Arc::new(Mutex::new(event_receiver)),
+                transport,
+                offline,
+                demo: flags.demo,
             },
             Command::none(),
         )
@@ -66,35 +173,104 @@ impl Application for RustRequester {
     }
 
     fn update(&mut self, incoming_message: Self::Message) -> Command<Self::Message> {
-        match incoming_message {
+        let command = match incoming_message {
+            Message::ProfileSelected(profile) => {
+                let switched = self.switch_profile(&profile);
+                self.show_profile_error(switched);
+                Command::none()
+            }
+            Message::NewProfileNameChanged(name) => {
+                self.new_profile_name = name;
+                Command::none()
+            }
+            Message::CreateProfile => {
+                let profile = std::mem::take(&mut self.new_profile_name);
+                if !profile.is_empty() {
+                    let switched = self.switch_profile(&profile).and_then(|()| {
+                        self.profiles = db::list_profiles(&self.db)?;
+                        Ok(())
+                    });
+                    self.show_profile_error(switched);
+                }
+                Command::none()
+            }
+            Message::ConfigMessage(config_inputs::Message::Discover) => self
+                .config_inputs
+                .discover(&mut self.config, &self.db, self.transport.clone())
+                .map(Message::ConfigMessage),
             Message::ConfigMessage(msg) => {
-                self.config = self
-                    .config_inputs
-                    .update(msg, &mut self.config, &self.db)
-                    .unwrap();
+                match self.config_inputs.update(msg, &mut self.config, &self.db) {
+                    Ok(config) => {
+                        self.config = config;
+                        self.profile_error = None;
+                    }
+                    Err(err) => {
+                        self.profile_error = Some(format!("Could not save profile: {}", err));
+                    }
+                }
                 Command::none()
             }
             Message::LabelRequestPanelMessage(msg) => self
                 .label_request_panel
                 .update(&self.config, &self.db, msg)
                 .map(Message::LabelRequestPanelMessage),
-        }
+        };
+        self.label_request_panel.refresh_status(&self.config, &self.db);
+        command
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
-        Subscription::none()
+        subscription::unfold("events", self.event_receiver.clone(), |receiver| async move {
+            let event = receiver.lock().await.recv().await;
+            let message = event.map(|event| {
+                Message::LabelRequestPanelMessage(label_request_panel::Message::Progress(event))
+            });
+            (message, receiver)
+        })
     }
 
-    fn view(&self) -> Element<Self::Message> {
-        Column::new()
-            .padding(20)
-            .spacing(20)
-            .push(Text::new("Rust Requester Configuration"))
-            .push(
+    fn view(&self) -> Element<'_, Self::Message> {
+        let profile_row = Row::new()
+            .spacing(10)
+            .push(Text::new("Profile:"))
+            .push(PickList::new(
+                &self.profiles[..],
+                Some(self.config.profile.clone()),
+                Message::ProfileSelected,
+            ))
+            .push(TextInput::new(
+                "NEW PROFILE",
+                &self.new_profile_name,
+                Message::NewProfileNameChanged,
+            ))
+            .push(Button::new(Text::new("Add")).on_press(Message::CreateProfile));
+        let profile_row = match &self.profile_error {
+            Some(error) => {
+                let style = ErrorStyle::Error;
+                profile_row.push(style.image()).push(Text::new(error).style(style.color()))
+            }
+            None => profile_row,
+        };
+
+        let header = match self.label_request_panel.account() {
+            Some(account) => format!("Rust Requester Configuration (signed in as {})", account),
+            None => "Rust Requester Configuration".to_string(),
+        };
+
+        let column = Column::new().padding(20).spacing(20);
+        let column = if self.demo {
+            column.push(Text::new(
+                "Rust Requester Demo: a made up Gmail account, and nothing is saved.",
+            ))
+        } else {
+            column.push(Text::new(header)).push(profile_row).push(
                 self.config_inputs
                     .view(&self.config)
                     .map(Message::ConfigMessage),
             )
+        };
+
+        column
             .push(
                 self.label_request_panel
                     .view()
@@ -103,3 +279,31 @@ impl Application for RustRequester {
             .into()
     }
 }
+
+impl RustRequester {
+    /// Everything on screen belongs to one profile, so switching starts the
+    /// inputs and the panel over, and cancels any flow in progress.
+    /// The profile is loaded before anything is cancelled or replaced, so a
+    /// database error leaves the current one as it was.
+    fn switch_profile(&mut self, profile: &str) -> Result<(), Error> {
+        let config = Configuration::init(&self.db, profile)?;
+        let _ = self.label_request_panel.update(
+            &self.config,
+            &self.db,
+            label_request_panel::Message::Cancel,
+        );
+        self.config = config;
+        self.config_inputs =
+            ConfigInputs::new(self.config.local_port.map(Port::as_u16), &self.config.http);
+        self.label_request_panel = LabelRequestPanel::new(
+            self.event_sender.clone(),
+            self.transport.clone(),
+            self.offline,
+        );
+        Ok(())
+    }
+
+    fn show_profile_error(&mut self, result: Result<(), Error>) {
+        self.profile_error = result.err().map(|err| format!("Could not load profile: {}", err));
+    }
+}
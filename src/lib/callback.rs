@@ -0,0 +1,64 @@
+use reqwest::Url;
+
+use crate::error::Error;
+
+/// Pull the authorization code out of whatever was pasted back by hand: the
+/// full redirect URL, just its query string, or the bare code on its own.
+/// Anything with a query string must carry the `state` we sent, same as at
+/// the listener. A bare code has no state to check, so it's taken on trust;
+/// `is_bare_code` tells the caller when that happened, so it can say so.
+pub fn parse_authorization_response(input: &str, expected_state: &str) -> Result<String, Error> {
+    match input.trim() {
+        "" => Err(Error::MissingToken),
+        code if is_bare_code(code) => Ok(code.to_string()),
+        redirect => parse_redirect(redirect, expected_state),
+    }
+}
+
+/// Whether `input` is just a code, with no query string and so no state.
+pub fn is_bare_code(input: &str) -> bool {
+    let input = input.trim();
+    !input.is_empty() && !input.contains('=')
+}
+
+/// The checks for a redirect, wherever it came from. This is what the
+/// listener uses directly.
+pub fn parse_redirect(redirect: &str, expected_state: &str) -> Result<String, Error> {
+    // Request targets like `/?code=...` and bare query strings need a base
+    // to be parsed against.
+    let base = Url::parse("http://localhost/")?;
+    let url = match redirect {
+        full if full.starts_with("http://") || full.starts_with("https://") => Url::parse(full),
+        relative if relative.starts_with('/') || relative.starts_with('?') => base.join(relative),
+        query => base.join(&format!("?{}", query)),
+    }
+    .map_err(|err| Error::UnexpectedResponse(format!("invalid redirect URL: {}", err)))?;
+
+    let mut code = None;
+    let mut state = None;
+    let mut error = None;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "code" => code = Some(value.into_owned()),
+            "state" => state = Some(value.into_owned()),
+            "error" => error = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+
+    if let Some(error) = error {
+        return Err(Error::AuthorizationDenied(error));
+    }
+
+    if state.as_deref() != Some(expected_state) {
+        return Err(Error::StateMismatch);
+    }
+
+    code.filter(|code| !code.is_empty()).ok_or(Error::MissingToken)
+}
+
+/// The target of an HTTP request line, e.g. `/?code=...&state=...` out of
+/// `GET /?code=...&state=... HTTP/1.1`.
+pub fn request_target(raw_request: &str) -> Option<&str> {
+    raw_request.lines().next()?.split_whitespace().nth(1)
+}
@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use oauth2::url::form_urlencoded;
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::configuration::HttpConfiguration;
+use crate::error::Error;
+use crate::transport::{BoxFuture, HttpRequest, HttpResponse, ReqwestTransport, Transport};
+
+/// Anything under one of these names, in a JSON body, a form or a query
+/// string, is replaced with `REDACTED` before it's written down. That goes
+/// for the query of any URL in a JSON body too, like the device flow's
+/// `verification_uri_complete`, which carries the user code.
+const SECRETS: [&str; 11] = [
+    "access_token",
+    "refresh_token",
+    "client_secret",
+    "code",
+    "code_verifier",
+    "code_challenge",
+    "device_code",
+    "user_code",
+    "token",
+    "state",
+    "nonce",
+];
+const REDACTED: &str = "REDACTED";
+
+/// What a run should send its requests through when it's recording to
+/// `record` or replaying `replay`. None when it's doing neither, and requests
+/// can go out as usual. Replaying wins if both are given. A recording goes
+/// out through a client built from `http`, same as it would unrecorded.
+pub fn transport(
+    record: Option<&Path>,
+    replay: Option<&Path>,
+    http: &HttpConfiguration,
+) -> Result<Option<Arc<dyn Transport>>, Error> {
+    let transport: Arc<dyn Transport> = match (record, replay) {
+        (_, Some(replay)) => Arc::new(Replayer::load(replay)?),
+        (Some(record), _) => {
+            let inner = Arc::new(ReqwestTransport::from_configuration(http)?);
+            Arc::new(Recorder::new(inner, record))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(transport))
+}
+
+/// One request and the response it got, as a cassette file holds them.
+/// Nothing here is secret any more, so cassettes can be attached to bug
+/// reports as they are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub request_body: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub response_body: String,
+}
+
+/// A transport that passes everything on to another one, and writes each
+/// exchange to a cassette file with the secrets scrubbed out. The file is
+/// rewritten after every exchange, so a run that dies halfway still leaves
+/// what it got.
+#[derive(Debug)]
+pub struct Recorder {
+    inner: Arc<dyn Transport>,
+    path: PathBuf,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Recorder {
+    pub fn new(inner: Arc<dyn Transport>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            path: path.into(),
+            interactions: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn record(&self, interaction: Interaction) -> Result<(), Error> {
+        // A panic elsewhere doesn't make the list any less writable.
+        let mut interactions = self.interactions.lock().unwrap_or_else(PoisonError::into_inner);
+        interactions.push(interaction);
+        // Reported as a configuration problem, like any other path that won't
+        // do.
+        let raw = serde_json::to_string_pretty(&*interactions)?;
+        std::fs::write(&self.path, raw).map_err(|err| {
+            let path = self.path.display();
+            Error::Configuration(format!("could not write cassette {}: {}", path, err))
+        })
+    }
+}
+
+impl Transport for Recorder {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, Error>> {
+        Box::pin(async move {
+            let (method, url, request_body) = scrub_request(&request);
+            let response = self.inner.send(request).await?;
+
+            let content_type = header(&response.headers);
+            self.record(Interaction {
+                method,
+                url,
+                request_body,
+                status: response.status_code.as_u16(),
+                response_body: scrub_body(&response.body, content_type.as_deref()),
+                content_type,
+            })?;
+
+            Ok(response)
+        })
+    }
+}
+
+/// A transport that never touches the network, answering from a cassette
+/// instead. Each request gets the first recorded response to the same
+/// method and URL that hasn't been used yet, so a flow that asks for the
+/// same thing twice gets the answers in the order they were recorded.
+///
+/// The tokens that come back are all `REDACTED`. That's fine, since they
+/// only ever get sent back here.
+#[derive(Debug)]
+pub struct Replayer {
+    interactions: Mutex<VecDeque<Interaction>>,
+}
+
+impl Replayer {
+    pub fn new(interactions: Vec<Interaction>) -> Self {
+        Self {
+            interactions: Mutex::new(interactions.into()),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path).map_err(|err| {
+            Error::Configuration(format!("could not read cassette {}: {}", path.display(), err))
+        })?;
+        let interactions = serde_json::from_str(&raw).map_err(|err| {
+            Error::Configuration(format!("could not parse cassette {}: {}", path.display(), err))
+        })?;
+        Ok(Self::new(interactions))
+    }
+
+    fn take(&self, method: &str, url: &str) -> Option<Interaction> {
+        let mut interactions = self.interactions.lock().unwrap_or_else(PoisonError::into_inner);
+        let position = interactions
+            .iter()
+            .position(|interaction| interaction.method == method && interaction.url == url)?;
+        interactions.remove(position)
+    }
+}
+
+impl Transport for Replayer {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, Error>> {
+        Box::pin(async move {
+            let (method, url, _) = scrub_request(&request);
+            let interaction = self.take(&method, &url).ok_or_else(|| {
+                Error::UnexpectedResponse(format!("nothing recorded for {} {}", method, url))
+            })?;
+
+            let status_code = StatusCode::from_u16(interaction.status).map_err(|_| {
+                Error::UnexpectedResponse(format!("recorded status {}", interaction.status))
+            })?;
+            let mut headers = HeaderMap::new();
+            if let Some(value) = interaction.content_type.and_then(|value| value.parse().ok()) {
+                headers.insert(CONTENT_TYPE, value);
+            }
+
+            Ok(HttpResponse {
+                status_code,
+                headers,
+                body: interaction.response_body.into_bytes(),
+            })
+        })
+    }
+}
+
+/// Method, URL and body, as they'd be written down. The URL's query is
+/// scrubbed too, since that's how it's matched on replay.
+fn scrub_request(request: &HttpRequest) -> (String, String, String) {
+    let mut url: Url = request.url.clone();
+    scrub_url(&mut url);
+    let body = scrub_body(&request.body, header(&request.headers).as_deref());
+
+    (request.method.to_string(), url.to_string(), body)
+}
+
+fn header(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// JSON and forms get their secrets scrubbed. Anything else is kept as is,
+/// since there's no telling where a secret would be in it.
+fn scrub_body(body: &[u8], content_type: Option<&str>) -> String {
+    let raw = String::from_utf8_lossy(body);
+    let content_type = content_type.unwrap_or_default();
+
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        return scrub_form(&raw);
+    }
+    match serde_json::from_str::<Value>(&raw) {
+        Ok(mut value) => {
+            scrub_json(&mut value);
+            value.to_string()
+        }
+        Err(_) => raw.into_owned(),
+    }
+}
+
+fn scrub_url(url: &mut Url) {
+    if let Some(query) = url.query().map(scrub_form) {
+        url.set_query(Some(&query));
+    }
+}
+
+fn scrub_form(form: &str) -> String {
+    let pairs = form_urlencoded::parse(form.as_bytes()).map(|(name, value)| {
+        let value = if SECRETS.contains(&name.as_ref()) { REDACTED.into() } else { value };
+        (name, value)
+    });
+    form_urlencoded::Serializer::new(String::new()).extend_pairs(pairs).finish()
+}
+
+/// ID tokens are dropped rather than redacted. A redacted one would only
+/// fail its signature check on replay, and without one there's nothing to
+/// check.
+fn scrub_json(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            fields.remove("id_token");
+            for (name, field) in fields.iter_mut() {
+                match field {
+                    Value::String(secret) if SECRETS.contains(&name.as_str()) => {
+                        *secret = REDACTED.to_string()
+                    }
+                    Value::String(text) => {
+                        if let Ok(mut url) = Url::parse(text) {
+                            if url.query().is_some() {
+                                scrub_url(&mut url);
+                                *text = url.to_string();
+                            }
+                        }
+                    }
+                    field => scrub_json(field),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(scrub_json),
+        _ => {}
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
+/// Whether the client can keep a secret. Desktop apps can't really, so many
+/// providers issue them as public clients and rely on PKCE alone.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ClientType {
+    #[default]
+    Confidential,
+    Public,
+}
+
+/// How a confidential client proves itself at the token endpoint, named as
+/// in the OAuth dynamic registration spec.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TokenAuthMethod {
+    #[default]
+    ClientSecretBasic,
+    ClientSecretPost,
+}
+
+impl ClientType {
+    pub const ALL: [ClientType; 2] = [ClientType::Confidential, ClientType::Public];
+}
+
+impl TokenAuthMethod {
+    pub const ALL: [TokenAuthMethod; 2] =
+        [TokenAuthMethod::ClientSecretBasic, TokenAuthMethod::ClientSecretPost];
+}
+
+impl Display for ClientType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Confidential => write!(f, "confidential"),
+            Self::Public => write!(f, "public"),
+        }
+    }
+}
+
+impl Display for TokenAuthMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ClientSecretBasic => write!(f, "client_secret_basic"),
+            Self::ClientSecretPost => write!(f, "client_secret_post"),
+        }
+    }
+}
+
+impl FromStr for ClientType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|client_type| client_type.to_string() == s)
+            .ok_or_else(|| format!("Unknown client type: {}", s))
+    }
+}
+
+impl FromStr for TokenAuthMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|method| method.to_string() == s)
+            .ok_or_else(|| format!("Unknown token auth method: {}", s))
+    }
+}
+
+// Both are stored in the database by name, so they can go straight through
+// the generic field updaters.
+impl ToSql for ClientType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl ToSql for TokenAuthMethod {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for ClientType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
+
+impl FromSql for TokenAuthMethod {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
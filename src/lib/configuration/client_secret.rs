@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::configuration::{port::Port, Configuration};
+use crate::error::Error;
+
+/// The `client_secret.json` that the Google Cloud Console hands out. It wraps
+/// the same fields in either an `installed` or a `web` object depending on
+/// the kind of client that was created.
+#[derive(Debug, Deserialize)]
+struct ClientSecretFile {
+    installed: Option<ClientSecret>,
+    web: Option<ClientSecret>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientSecret {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub auth_uri: String,
+    pub token_uri: String,
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+}
+
+impl ClientSecret {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        // Read errors are reported as configuration problems, naming the
+        // file, since it's the path that needs fixing.
+        let raw_json = std::fs::read_to_string(path).map_err(|err| {
+            Error::Configuration(format!("Could not read {}: {}", path.display(), err))
+        })?;
+
+        Self::from_json_str(&raw_json)
+    }
+
+    pub fn from_json_str(raw_json: &str) -> Result<Self, Error> {
+        let file: ClientSecretFile = serde_json::from_str(raw_json)?;
+
+        file.installed.or(file.web).ok_or_else(|| {
+            Error::Configuration("Client secret file has no installed or web client.".to_string())
+        })
+    }
+
+    /// The first loopback redirect with an explicit port. Desktop clients
+    /// usually only list a bare `http://localhost`, in which case any port
+    /// will do and the configured one is kept.
+    pub fn redirect_port(&self) -> Option<Port> {
+        self.redirect_uris
+            .iter()
+            .filter_map(|uri| Url::parse(uri).ok())
+            .filter(|url| matches!(url.host_str(), Some("localhost") | Some("127.0.0.1")))
+            .find_map(|url| url.port())
+            .map(Port::from)
+    }
+
+    /// Overlay these credentials onto a configuration without saving them.
+    pub fn apply(&self, mut config: Configuration) -> Configuration {
+        config.api.id = Some(self.client_id.clone());
+        config.api.secret = self.client_secret.clone().or(config.api.secret);
+        config.api.auth_url = Some(self.auth_uri.clone());
+        config.api.token_url = Some(self.token_uri.clone());
+        config.local_port = self.redirect_port().or(config.local_port);
+        config
+    }
+}
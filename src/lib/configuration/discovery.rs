@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::Configuration;
+use crate::db;
+use crate::error::Error;
+use crate::token::now;
+use crate::transport::{self, Transport};
+
+/// How long a discovered document is trusted before it's fetched again.
+/// Providers rarely move their endpoints, so a day is plenty fresh.
+pub const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const WELL_KNOWN_PATH: &str = "/.well-known/openid-configuration";
+
+/// The parts of an OpenID Connect discovery document we use. The rest of
+/// it is kept in the cache as fetched, but ignored here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub device_authorization_endpoint: Option<String>,
+    pub revocation_endpoint: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: Option<String>,
+}
+
+impl ProviderMetadata {
+    /// The spec requires the document to name the issuer it was fetched
+    /// for, which stops one provider's document being passed off as another's.
+    pub fn from_json_str(raw: &str, issuer: &str) -> Result<Self, Error> {
+        let metadata: ProviderMetadata = serde_json::from_str(raw)?;
+
+        if metadata.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(Error::UnexpectedResponse(format!(
+                "discovery document is for issuer {}, not {}",
+                metadata.issuer, issuer
+            )));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Overlay the discovered endpoints onto a configuration without saving
+    /// them. The device, revocation and userinfo endpoints are optional, so
+    /// a configured one is kept if the provider doesn't list its own.
+    pub fn apply(&self, mut config: Configuration) -> Configuration {
+        config.api.auth_url = Some(self.authorization_endpoint.clone());
+        config.api.token_url = Some(self.token_endpoint.clone());
+        config.api.device_auth_url = self
+            .device_authorization_endpoint
+            .clone()
+            .or(config.api.device_auth_url);
+        config.api.revocation_url = self.revocation_endpoint.clone().or(config.api.revocation_url);
+        config.api.userinfo_url = self.userinfo_endpoint.clone().or(config.api.userinfo_url);
+        config.api.jwks_url = self.jwks_uri.clone().or(config.api.jwks_url);
+        config
+    }
+}
+
+/// The cached document for an issuer, and whether it's still within
+/// `MAX_AGE`.
+pub fn cached(issuer: &str, db: &Connection) -> Result<Option<(ProviderMetadata, bool)>, Error> {
+    let (document, fetched_at) = match db::get_discovery(issuer, db)? {
+        Some(cached) => cached,
+        None => return Ok(None),
+    };
+    let fresh = now().saturating_sub(fetched_at) < MAX_AGE.as_secs();
+
+    Ok(Some((ProviderMetadata::from_json_str(&document, issuer)?, fresh)))
+}
+
+/// Fetch the document straight from the issuer, ignoring the cache. This
+/// doesn't touch the database, so the GUI can run it off the main thread.
+/// The raw document is returned alongside, for `store`.
+pub async fn fetch(
+    issuer: String,
+    transport: Arc<dyn Transport>,
+) -> Result<(ProviderMetadata, String), Error> {
+    let url = format!("{}{}", issuer.trim_end_matches('/'), WELL_KNOWN_PATH);
+
+    let raw = transport::get(transport.as_ref(), &url).await?;
+
+    Ok((ProviderMetadata::from_json_str(&raw, &issuer)?, raw))
+}
+
+pub fn store(issuer: &str, raw: &str, db: &Connection) -> Result<(), Error> {
+    db::save_discovery(issuer, raw, now(), db)
+}
+
+/// What `discover` settled on. `stale` is why the issuer couldn't be
+/// reached, when an out of date cached copy was used instead. It's up to
+/// the caller whether that's worth mentioning.
+#[derive(Debug, Clone)]
+pub struct Discovered {
+    pub metadata: ProviderMetadata,
+    pub stale: Option<Error>,
+}
+
+/// The cached document if it's fresh, otherwise a new one from the issuer.
+/// If the issuer can't be reached, a stale copy beats no copy at all.
+pub async fn discover(
+    issuer: &str,
+    db: &Connection,
+    refresh: bool,
+    transport: Arc<dyn Transport>,
+) -> Result<Discovered, Error> {
+    let cached = cached(issuer, db)?;
+
+    if let Some((metadata, true)) = &cached {
+        if !refresh {
+            return Ok(Discovered { metadata: metadata.clone(), stale: None });
+        }
+    }
+
+    match fetch(issuer.to_string(), transport).await {
+        Ok((metadata, raw)) => {
+            store(issuer, &raw, db)?;
+            Ok(Discovered { metadata, stale: None })
+        }
+        Err(err) => match cached {
+            Some((metadata, _)) => Ok(Discovered { metadata, stale: Some(err) }),
+            None => Err(err),
+        },
+    }
+}
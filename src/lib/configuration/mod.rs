@@ -1,10 +1,19 @@
 pub use rusqlite::{params, Connection};
 
+pub mod client_auth;
+pub mod client_secret;
+pub mod discovery;
 pub mod port;
 
+use std::path::Path;
+
+use crate::configuration::client_auth::{ClientType, TokenAuthMethod};
+use crate::configuration::client_secret::ClientSecret;
+use crate::configuration::discovery::ProviderMetadata;
 use crate::configuration::port::Port;
 use crate::db;
 use crate::error::Error;
+use crate::provider::ProviderKind;
 
 /// We're using a macro here, and generics in the db, mostly as proof of 
 /// knowledge, if you will. Neither is strictly necessary for the field
@@ -12,9 +21,7 @@ use crate::error::Error;
 macro_rules! field_updater {
     ($field:ident, $enum:ident, $type:ty) => {
         pub fn $field(&mut self, db: &rusqlite::Connection, value: $type) -> Result<(), Error> {
-            db::update_config::<$type>(db::EditableConfigFields::$enum, value, &db)
-                .map_err(Box::from)?;
-            Ok(())
+            db::update_config::<$type>(db::EditableConfigFields::$enum, value, &self.profile, &db)
         }
     };
 }
@@ -23,46 +30,106 @@ macro_rules! field_updater {
 /// more than I'm happy with. 
 #[derive(Debug, Clone)]
 pub struct Configuration {
+    /// Which set of credentials, tokens and labels this is.
+    pub profile: String,
     pub api: ApiConfiguration,
     pub local_port: Option<Port>,
+    pub http: HttpConfiguration,
 }
 
 #[derive(Debug, Clone)]
 pub struct ApiConfiguration {
     pub id: Option<String>,
     pub secret: Option<String>,
+    /// Google's endpoints are known, so for it these are only overrides.
     pub auth_url: Option<String>,
     pub token_url: Option<String>,
+    /// Only needed for the device flow, so it isn't part of `is_complete`.
+    pub device_auth_url: Option<String>,
+    pub provider: ProviderKind,
+    /// Space separated, as OAuth writes them. Only used by custom providers.
+    pub scopes: Option<String>,
+    /// The collection to list. Only used by custom providers.
+    pub resource_url: Option<String>,
+    /// With this set, the endpoints can be discovered rather than typed in.
+    pub issuer_url: Option<String>,
+    pub revocation_url: Option<String>,
+    pub userinfo_url: Option<String>,
+    /// The issuer's signing keys, for checking ID tokens.
+    pub jwks_url: Option<String>,
+    /// Public clients have no secret to send.
+    pub client_type: ClientType,
+    pub token_auth_method: TokenAuthMethod,
+}
+
+/// How to reach the servers at all, for networks that don't let you just
+/// connect. Unset fields fall back to the defaults in `transport`.
+#[derive(Debug, Clone, Default)]
+pub struct HttpConfiguration {
+    /// In seconds.
+    pub connect_timeout: Option<u64>,
+    /// In seconds, the longest a response can go without sending anything.
+    pub read_timeout: Option<u64>,
+    pub user_agent: Option<String>,
+    /// Everything goes through this proxy, HTTP and HTTPS alike.
+    pub proxy_url: Option<String>,
+    /// A PEM file of extra CA certificates to trust, on top of the system's.
+    pub ca_bundle_path: Option<String>,
 }
 
 impl Configuration {
-    fn new() -> Self {
+    fn new(profile: &str) -> Self {
         Self {
+            profile: profile.to_string(),
             api: ApiConfiguration::new(),
             local_port: None,
+            http: HttpConfiguration::default(),
         }
     }
 
-    pub fn init(db: &Connection) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Load a profile's configuration, creating the profile if it's new.
+    pub fn init(db: &Connection, profile: &str) -> Result<Self, Error> {
+        db::create_profile(profile, db)?;
+
         // Select the config from the database
-        let mut stmt = db.prepare(db::SELECT_CONFIG).unwrap();
+        let mut stmt = db.prepare(db::SELECT_CONFIG)?;
         let config: Configuration = stmt
-            .query_map(params![], |row| {
+            .query_map(params![profile], |row| {
                 Ok(Configuration {
+                    profile: profile.to_string(),
                     api: ApiConfiguration {
                         id: row.get(0).ok(),
                         secret: row.get(1).ok(),
                         auth_url: row.get(2).ok(),
                         token_url: row.get(3).ok(),
+                        device_auth_url: row.get(5).ok(),
+                        client_type: row.get(6).unwrap_or_default(),
+                        token_auth_method: row.get(7).unwrap_or_default(),
+                        provider: row.get(8).unwrap_or_default(),
+                        scopes: row.get(9).ok(),
+                        resource_url: row.get(10).ok(),
+                        issuer_url: row.get(11).ok(),
+                        revocation_url: row.get(12).ok(),
+                        userinfo_url: row.get(13).ok(),
+                        jwks_url: row.get(14).ok(),
                     },
                     local_port: row.get::<_, u16>(4).map(|u| u.into()).ok(),
+                    // A cleared GUI box is saved as an empty string. For these
+                    // that means the default, not a proxy or CA bundle at "".
+                    http: HttpConfiguration {
+                        connect_timeout: row.get(15).ok(),
+                        read_timeout: row.get(16).ok(),
+                        user_agent: row.get(17).ok().filter(|agent: &String| !agent.is_empty()),
+                        proxy_url: row.get(18).ok().filter(|url: &String| !url.is_empty()),
+                        ca_bundle_path: row.get(19).ok().filter(|path: &String| !path.is_empty()),
+                    },
                 })
                 // In order: propagate query errors,
                 // default a new Configuration if empty table,
                 // and propagate any mapping errors
             })?
             .next()
-            .unwrap_or(Ok(Configuration::new()))?;
+            .unwrap_or_else(|| Ok(Configuration::new(profile)))?;
 
         Ok(config)
     }
@@ -72,6 +139,22 @@ impl Configuration {
     field_updater!(update_auth_url, AuthUrl, String);
     field_updater!(update_token_url, TokenUrl, String);
     field_updater!(update_local_port, LocalPort, u16);
+    field_updater!(update_device_auth_url, DeviceAuthUrl, String);
+    field_updater!(update_client_type, ClientType, ClientType);
+    field_updater!(update_token_auth_method, TokenAuthMethod, TokenAuthMethod);
+    field_updater!(update_provider, Provider, ProviderKind);
+    field_updater!(update_scopes, Scopes, String);
+    field_updater!(update_resource_url, ResourceUrl, String);
+    field_updater!(update_issuer_url, IssuerUrl, String);
+    field_updater!(update_revocation_url, RevocationUrl, String);
+    field_updater!(update_userinfo_url, UserinfoUrl, String);
+    field_updater!(update_jwks_url, JwksUrl, String);
+    // None puts them back to the default.
+    field_updater!(update_connect_timeout, ConnectTimeout, Option<u64>);
+    field_updater!(update_read_timeout, ReadTimeout, Option<u64>);
+    field_updater!(update_user_agent, UserAgent, String);
+    field_updater!(update_proxy_url, ProxyUrl, String);
+    field_updater!(update_ca_bundle_path, CaBundlePath, String);
 
     pub fn update_config(
         &mut self,
@@ -81,7 +164,7 @@ impl Configuration {
         token_url: Option<String>,
         local_port: Option<Port>,
         db: &Connection,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, Error> {
 
         db::update_full_config(
             id.clone(),
@@ -89,27 +172,84 @@ impl Configuration {
             auth_url.clone(),
             token_url.clone(),
             local_port.map(|p| p.as_u16()),
+            &self.profile,
             db,
         )?;
 
         Ok(Self {
+            profile: self.profile.clone(),
             api: ApiConfiguration {
                 id,
                 secret,
                 auth_url,
                 token_url,
+                device_auth_url: self.api.device_auth_url.clone(),
+                client_type: self.api.client_type,
+                token_auth_method: self.api.token_auth_method,
+                provider: self.api.provider,
+                scopes: self.api.scopes.clone(),
+                resource_url: self.api.resource_url.clone(),
+                issuer_url: self.api.issuer_url.clone(),
+                revocation_url: self.api.revocation_url.clone(),
+                userinfo_url: self.api.userinfo_url.clone(),
+                jwks_url: self.api.jwks_url.clone(),
             },
-            local_port: local_port.map(|u| u.into()),
+            local_port,
+            http: self.http.clone(),
         })
     }
 
+    /// Read a `client_secret.json` from the Google Cloud Console and save
+    /// its credentials, rather than copying the fields over by hand.
+    pub fn import_client_secret(
+        &mut self,
+        path: impl AsRef<Path>,
+        db: &Connection,
+    ) -> Result<Self, Error> {
+        let imported = ClientSecret::from_file(path)?.apply(self.clone());
+
+        self.update_config(
+            imported.api.id,
+            imported.api.secret,
+            imported.api.auth_url,
+            imported.api.token_url,
+            imported.local_port,
+            db,
+        )
+    }
+
+    /// Save the endpoints from a discovery document, so they show up in the
+    /// inputs and `config show` like any others.
+    pub fn import_discovery(
+        &mut self,
+        metadata: &ProviderMetadata,
+        db: &Connection,
+    ) -> Result<Self, Error> {
+        let imported = metadata.apply(self.clone());
+        let api = imported.api.clone();
+
+        let fields = [
+            (db::EditableConfigFields::AuthUrl, api.auth_url),
+            (db::EditableConfigFields::TokenUrl, api.token_url),
+            (db::EditableConfigFields::DeviceAuthUrl, api.device_auth_url),
+            (db::EditableConfigFields::RevocationUrl, api.revocation_url),
+            (db::EditableConfigFields::UserinfoUrl, api.userinfo_url),
+            (db::EditableConfigFields::JwksUrl, api.jwks_url),
+        ];
+        for (field, value) in fields {
+            db::update_config(field, value, &self.profile, db)?;
+        }
+
+        Ok(imported)
+    }
+
     pub fn is_complete(&self) -> bool {
         self.api.is_complete()
     }
 
-    pub fn reset(db: &Connection) -> Result<Self, Box<dyn std::error::Error>> {
-        db::reset_config(db)?;
-        Ok(Self::new())
+    pub fn reset(db: &Connection, profile: &str) -> Result<Self, Error> {
+        db::reset_config(profile, db)?;
+        Ok(Self::new(profile))
     }
 }
 
@@ -120,13 +260,39 @@ impl ApiConfiguration {
             secret: None,
             auth_url: None,
             token_url: None,
+            device_auth_url: None,
+            client_type: ClientType::default(),
+            token_auth_method: TokenAuthMethod::default(),
+            provider: ProviderKind::default(),
+            scopes: None,
+            resource_url: None,
+            issuer_url: None,
+            revocation_url: None,
+            userinfo_url: None,
+            jwks_url: None,
         }
     }
 
     pub fn is_complete(&self) -> bool {
-        self.id.is_some()
-            && self.secret.is_some()
-            && self.auth_url.is_some()
-            && self.token_url.is_some()
+        self.missing_fields().is_empty()
+    }
+
+    /// The required fields that are still unset, for telling the user exactly
+    /// what to supply instead of just refusing.
+    pub fn missing_fields(&self) -> Vec<db::EditableConfigFields> {
+        // A public client's secret is optional, and ignored if present.
+        let custom = self.provider == ProviderKind::Custom;
+        [
+            (self.id.is_none(), db::EditableConfigFields::ApiId),
+            (
+                self.secret.is_none() && self.client_type == ClientType::Confidential,
+                db::EditableConfigFields::ApiSecret,
+            ),
+            (custom && self.auth_url.is_none(), db::EditableConfigFields::AuthUrl),
+            (custom && self.token_url.is_none(), db::EditableConfigFields::TokenUrl),
+        ]
+        .into_iter()
+        .filter_map(|(missing, field)| missing.then_some(field))
+        .collect()
     }
 }
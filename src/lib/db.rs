@@ -1,15 +1,39 @@
 pub use rusqlite::{params, Connection};
-use std::{fmt::Display};
+use rusqlite::{backup::Backup, OpenFlags};
+use std::{fmt::Display, path::Path, time::Duration};
 use crate::error::Error;
+use crate::label::Label;
+
+/// The profile every database starts with, and the one the row from before
+/// profiles existed was migrated to.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const DB_PATH: &str = "rust_requester.db";
 
 /// A sanitized, whitelisted set of fields that we can
 /// plug into queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EditableConfigFields {
     ApiId,
     ApiSecret,
     AuthUrl,
     TokenUrl,
-    LocalPort
+    LocalPort,
+    DeviceAuthUrl,
+    ClientType,
+    TokenAuthMethod,
+    Provider,
+    Scopes,
+    ResourceUrl,
+    IssuerUrl,
+    RevocationUrl,
+    UserinfoUrl,
+    JwksUrl,
+    ConnectTimeout,
+    ReadTimeout,
+    UserAgent,
+    ProxyUrl,
+    CaBundlePath,
 }
 
 impl Display for EditableConfigFields {
@@ -20,13 +44,36 @@ impl Display for EditableConfigFields {
             Self::AuthUrl => write!(f, "auth_url"),
             Self::TokenUrl => write!(f, "token_url"),
             Self::LocalPort => write!(f, "local_port"),
+            Self::DeviceAuthUrl => write!(f, "device_auth_url"),
+            Self::ClientType => write!(f, "client_type"),
+            Self::TokenAuthMethod => write!(f, "token_auth_method"),
+            Self::Provider => write!(f, "provider"),
+            Self::Scopes => write!(f, "scopes"),
+            Self::ResourceUrl => write!(f, "resource_url"),
+            Self::IssuerUrl => write!(f, "issuer_url"),
+            Self::RevocationUrl => write!(f, "revocation_url"),
+            Self::UserinfoUrl => write!(f, "userinfo_url"),
+            Self::JwksUrl => write!(f, "jwks_url"),
+            Self::ConnectTimeout => write!(f, "connect_timeout"),
+            Self::ReadTimeout => write!(f, "read_timeout"),
+            Self::UserAgent => write!(f, "user_agent"),
+            Self::ProxyUrl => write!(f, "proxy_url"),
+            Self::CaBundlePath => write!(f, "ca_bundle_path"),
         }
     }
 }
 
 /// Initializes sqlite3 database, creates config table if it doesn't exist.
 pub fn init() -> Result<Connection, Error> {
-    let db = open()?;
+    create_tables(open()?)
+}
+
+/// A fresh, empty database that only lives in memory, for the demo.
+pub fn init_in_memory() -> Result<Connection, Error> {
+    create_tables(Connection::open_in_memory()?)
+}
+
+fn create_tables(db: Connection) -> Result<Connection, Error> {
     db.execute(
         "CREATE TABLE IF NOT EXISTS config (
             id              INTEGER PRIMARY KEY,
@@ -37,32 +84,145 @@ pub fn init() -> Result<Connection, Error> {
             local_port      INTEGER
         );",
         params![],
-    ).map_err(Box::from)?;
+    )?;
 
-    db.execute("DROP TABLE IF EXISTS labels;", params![]).map_err(Box::from)?;
+    db.execute("DROP TABLE IF EXISTS labels;", params![])?;
 
     db.execute(
         "CREATE TABLE IF NOT EXISTS labels (
             id              INTEGER PRIMARY KEY,
+            profile         TEXT,
+            account         TEXT,
             name            TEXT,
             postcard        BLOB
         );",
         params![],
-    ).map_err(Box::from)?;
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS tokens (
+            id              INTEGER PRIMARY KEY,
+            access_token    TEXT NOT NULL,
+            refresh_token   TEXT,
+            expires_at      INTEGER,
+            scopes          TEXT
+        );",
+        params![],
+    )?;
+
+    migrate(&db)?;
 
-    db.execute("INSERT OR IGNORE INTO config (id) VALUES (1)", params![]).map_err(Box::from)?;
+    create_profile(DEFAULT_PROFILE, &db)?;
 
     Ok(db)
 }
 
-pub fn reset_config(db: &Connection) -> Result<(), Error> {
-    db.execute("REPLACE INTO config (id) VALUES (1)", params![]).map_err(Box::from)?;
+/// A copy of the database in memory, for runs that shouldn't leave a mark,
+/// like replaying a cassette. It starts out as whatever is saved, and is gone
+/// once dropped. The saved file is only ever read, so one from an older
+/// version is migrated in the copy, and a missing one isn't created.
+pub fn init_scratch() -> Result<Connection, Error> {
+    let mut db = Connection::open_in_memory()?;
+
+    if Path::new(DB_PATH).exists() {
+        let saved = Connection::open_with_flags(DB_PATH, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Backup::new(&saved, &mut db)?.run_to_completion(
+            100,
+            Duration::ZERO,
+            None::<fn(rusqlite::backup::Progress)>,
+        )?;
+    }
+
+    create_tables(db)
+}
+
+/// Schema changes made after the tables above were first shipped. Each one
+/// runs exactly once, in order, and sqlite's `user_version` records how many
+/// have been applied. Only ever append to this list.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE config ADD COLUMN device_auth_url TEXT;",
+    "ALTER TABLE config ADD COLUMN client_type TEXT;
+     ALTER TABLE config ADD COLUMN token_auth_method TEXT;",
+    // Everything used to live in row 1, so that becomes the default profile.
+    "ALTER TABLE config ADD COLUMN profile TEXT;
+     UPDATE config SET profile = 'default' WHERE id = 1;
+     CREATE UNIQUE INDEX config_profile ON config (profile);
+     ALTER TABLE tokens ADD COLUMN profile TEXT;
+     UPDATE tokens SET profile = 'default' WHERE id = 1;
+     CREATE UNIQUE INDEX tokens_profile ON tokens (profile);",
+    "ALTER TABLE config ADD COLUMN provider TEXT;
+     ALTER TABLE config ADD COLUMN scopes TEXT;
+     ALTER TABLE config ADD COLUMN resource_url TEXT;",
+    "ALTER TABLE config ADD COLUMN issuer_url TEXT;
+     ALTER TABLE config ADD COLUMN revocation_url TEXT;
+     ALTER TABLE config ADD COLUMN userinfo_url TEXT;
+     CREATE TABLE discovery (
+        issuer          TEXT PRIMARY KEY,
+        document        TEXT NOT NULL,
+        fetched_at      INTEGER NOT NULL
+     );",
+    "ALTER TABLE config ADD COLUMN jwks_url TEXT;
+     ALTER TABLE tokens ADD COLUMN email TEXT;
+     CREATE TABLE jwks (
+        url             TEXT PRIMARY KEY,
+        document        TEXT NOT NULL,
+        fetched_at      INTEGER NOT NULL
+     );",
+    "ALTER TABLE tokens ADD COLUMN refreshed_at INTEGER;",
+    // Five statements in one entry are fine, `migrate` applies the lot or
+    // none of it.
+    "ALTER TABLE config ADD COLUMN connect_timeout INTEGER;
+     ALTER TABLE config ADD COLUMN read_timeout INTEGER;
+     ALTER TABLE config ADD COLUMN user_agent TEXT;
+     ALTER TABLE config ADD COLUMN proxy_url TEXT;
+     ALTER TABLE config ADD COLUMN ca_bundle_path TEXT;",
+];
+
+/// Each migration commits together with its version bump, or not at all. A
+/// half applied one would otherwise leave its first columns behind, and
+/// every later start would trip over them as duplicates.
+fn migrate(db: &Connection) -> Result<(), Error> {
+    let applied: usize = db.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let transaction = db.unchecked_transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", version + 1)?;
+        transaction.commit()?;
+    }
+
     Ok(())
 }
 
-pub fn update_config<T: rusqlite::ToSql>(field: EditableConfigFields, value: T, db: &Connection) -> Result<(), Error> {
-    db.prepare(format!("UPDATE config SET {} = ? WHERE id = 1", field).as_str()).map_err(Box::from)?
-        .execute(params![value]).map_err(Box::from)?;
+/// Profiles are just config rows, so creating one is cheap and does nothing
+/// if it already exists.
+pub fn create_profile(profile: &str, db: &Connection) -> Result<(), Error> {
+    db.execute("INSERT OR IGNORE INTO config (profile) VALUES (?)", params![profile])?;
+    Ok(())
+}
+
+pub fn list_profiles(db: &Connection) -> Result<Vec<String>, Error> {
+    let profiles = db
+        .prepare("SELECT profile FROM config WHERE profile IS NOT NULL ORDER BY id")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+    Ok(profiles)
+}
+
+pub fn reset_config(profile: &str, db: &Connection) -> Result<(), Error> {
+    db.execute("REPLACE INTO config (profile) VALUES (?)", params![profile])?;
+    Ok(())
+}
+
+pub fn update_config<T: rusqlite::ToSql>(
+    field: EditableConfigFields,
+    value: T,
+    profile: &str,
+    db: &Connection,
+) -> Result<(), Error> {
+    db.prepare(format!("UPDATE config SET {} = ? WHERE profile = ?", field).as_str())?
+        .execute(params![value, profile])?;
 
     Ok(())
 }
@@ -73,31 +233,123 @@ pub fn update_full_config(
     auth_url: Option<String>,
     token_url: Option<String>,
     local_port: Option<u16>,
+    profile: &str,
     db: &Connection,
 ) -> Result<(), Error> {
     db.execute(
-        "UPDATE config SET api_id = ?, api_secret = ?, auth_url = ?, token_url = ?, local_port = ? WHERE id = 1",
-        params![id, secret, auth_url, token_url, local_port]
-    ).map_err(Box::from)?;
+        "UPDATE config SET api_id = ?, api_secret = ?, auth_url = ?, token_url = ?, local_port = ? WHERE profile = ?",
+        params![id, secret, auth_url, token_url, local_port, profile]
+    )?;
 
     Ok(())
 }
 
 pub const SELECT_CONFIG: &str =
-    "SELECT api_id, api_secret, auth_url, token_url, local_port FROM config WHERE id = 1";
+    "SELECT api_id, api_secret, auth_url, token_url, local_port, device_auth_url, client_type, token_auth_method, provider, scopes, resource_url, issuer_url, revocation_url, userinfo_url, jwks_url, connect_timeout, read_timeout, user_agent, proxy_url, ca_bundle_path FROM config WHERE profile = ?";
+
+pub const SELECT_TOKEN: &str =
+    "SELECT access_token, refresh_token, expires_at, scopes, email, refreshed_at FROM tokens WHERE profile = ?";
+
+#[allow(clippy::too_many_arguments)]
+pub fn save_token(
+    access_token: &str,
+    refresh_token: Option<&str>,
+    expires_at: Option<u64>,
+    scopes: &str,
+    email: Option<&str>,
+    refreshed_at: Option<u64>,
+    profile: &str,
+    db: &Connection,
+) -> Result<(), Error> {
+    db.execute(
+        "REPLACE INTO tokens (profile, access_token, refresh_token, expires_at, scopes, email, refreshed_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![profile, access_token, refresh_token, expires_at, scopes, email, refreshed_at],
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_token(profile: &str, db: &Connection) -> Result<(), Error> {
+    db.execute("DELETE FROM tokens WHERE profile = ?", params![profile])?;
+    Ok(())
+}
+
+/// A cached discovery document, and when it was fetched.
+pub fn get_discovery(issuer: &str, db: &Connection) -> Result<Option<(String, u64)>, Error> {
+    let cached = db
+        .prepare("SELECT document, fetched_at FROM discovery WHERE issuer = ?")?
+        .query_map(params![issuer], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .next()
+        .transpose()?;
+
+    Ok(cached)
+}
+
+pub fn save_discovery(
+    issuer: &str,
+    document: &str,
+    fetched_at: u64,
+    db: &Connection,
+) -> Result<(), Error> {
+    db.execute(
+        "REPLACE INTO discovery (issuer, document, fetched_at) VALUES (?, ?, ?)",
+        params![issuer, document, fetched_at],
+    )?;
+
+    Ok(())
+}
+
+/// A cached JWKS document, and when it was fetched.
+pub fn get_jwks(url: &str, db: &Connection) -> Result<Option<(String, u64)>, Error> {
+    let cached = db
+        .prepare("SELECT document, fetched_at FROM jwks WHERE url = ?")?
+        .query_map(params![url], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .next()
+        .transpose()?;
+
+    Ok(cached)
+}
+
+pub fn save_jwks(url: &str, document: &str, fetched_at: u64, db: &Connection) -> Result<(), Error> {
+    db.execute(
+        "REPLACE INTO jwks (url, document, fetched_at) VALUES (?, ?, ?)",
+        params![url, document, fetched_at],
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_labels(profile: &str, db: &Connection) -> Result<(), Error> {
+    db.execute("DELETE FROM labels WHERE profile = ?", params![profile])?;
+    Ok(())
+}
 
 fn open() -> Result<Connection, Error> {
-    let db = Connection::open("rust_requester.db").map_err(Box::from)?;
+    let db = Connection::open(DB_PATH)?;
     Ok(db)
 }
 
-pub fn get_labels(db: &Connection) -> Vec<Result<String, rusqlite::Error>> {
-    db.prepare("SELECT name FROM labels")
-        .expect("Failed to prepare query.")
-        .query_map([], |row| {
+/// The saved labels in full, decoded from their postcard blobs.
+pub fn get_full_labels(profile: &str, db: &Connection) -> Result<Vec<Label>, Error> {
+    let blobs = db
+        .prepare("SELECT postcard FROM labels WHERE profile = ? ORDER BY id")?
+        .query_map(params![profile], |row| row.get::<_, Vec<u8>>(0))?
+        .collect::<Result<Vec<Vec<u8>>, rusqlite::Error>>()?;
+
+    blobs
+        .iter()
+        .map(|blob| postcard::from_bytes(blob).map_err(|err| Error::from(Box::from(err))))
+        .collect()
+}
+
+pub fn get_labels(profile: &str, db: &Connection) -> Result<Vec<String>, Error> {
+    let names = db
+        .prepare("SELECT name FROM labels WHERE profile = ?")?
+        .query_map(params![profile], |row| {
             let name: String = row.get(0).unwrap_or("UNNAMED".to_owned());
             Ok(name)
-        })
-        .expect("Failed to query labels.")
-        .collect()
+        })?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+    Ok(names)
 }
@@ -0,0 +1,166 @@
+use std::sync::{Mutex, PoisonError};
+
+use reqwest::{
+    header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+    Method, StatusCode,
+};
+use serde_json::{json, Value};
+
+use crate::configuration::{
+    client_auth::{ClientType, TokenAuthMethod},
+    ApiConfiguration, Configuration, HttpConfiguration,
+};
+use crate::error::Error;
+use crate::fake_gmail::{self, label};
+use crate::provider::ProviderKind;
+use crate::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+
+pub const PROFILE: &str = "demo";
+
+/// Gmail's own label colors, text then background.
+const BLUE: (&str, &str) = ("#ffffff", "#4a86e8");
+const GREEN: (&str, &str) = ("#ffffff", "#16a766");
+const PINK: (&str, &str) = ("#ffffff", "#f691b3");
+const YELLOW: (&str, &str) = ("#000000", "#fad165");
+const PURPLE: (&str, &str) = ("#ffffff", "#a479e2");
+
+/// A Google profile with a made up client id and nothing else, for poking
+/// around without any credentials. It only works with `Demo` as the
+/// transport, which answers everything it would send.
+pub fn configuration() -> Configuration {
+    Configuration {
+        profile: PROFILE.to_string(),
+        api: ApiConfiguration {
+            id: Some("demo-client".to_string()),
+            secret: None,
+            auth_url: None,
+            token_url: None,
+            device_auth_url: None,
+            provider: ProviderKind::Google,
+            scopes: None,
+            resource_url: None,
+            issuer_url: None,
+            revocation_url: None,
+            userinfo_url: None,
+            jwks_url: None,
+            client_type: ClientType::Public,
+            token_auth_method: TokenAuthMethod::default(),
+        },
+        local_port: None,
+        http: HttpConfiguration::default(),
+    }
+}
+
+/// Stands in for Google's token, device and revocation endpoints and the
+/// labels API, without the network, for `configuration`. Any code, device
+/// code or refresh token gets a token, and anything can be revoked.
+///
+/// The labels are a made up mailbox's: system labels, nested user labels,
+/// colors and counts, shaped the way Gmail sends them so they go through
+/// `process` and the database like real ones. Labels created or deleted
+/// stick around until it's dropped.
+#[derive(Debug)]
+pub struct Demo {
+    labels: Mutex<fake_gmail::Labels>,
+}
+
+impl Default for Demo {
+    fn default() -> Self {
+        let system = |id: &str, total: u32, unread: u32| {
+            label(id, id, "system", None, total, unread)
+        };
+        let user = |id: &str, name: &str, color, total: u32, unread: u32| {
+            label(id, name, "user", color, total, unread)
+        };
+
+        Self {
+            labels: Mutex::new(fake_gmail::Labels::new(vec![
+                system("INBOX", 1342, 27),
+                system("SENT", 864, 0),
+                system("DRAFT", 3, 0),
+                system("STARRED", 41, 2),
+                system("IMPORTANT", 402, 11),
+                system("UNREAD", 58, 58),
+                system("SPAM", 19, 19),
+                system("TRASH", 77, 0),
+                system("CATEGORY_SOCIAL", 210, 6),
+                system("CATEGORY_PROMOTIONS", 655, 31),
+                system("CATEGORY_UPDATES", 388, 9),
+                user("Label_1", "Work", Some(BLUE), 512, 8),
+                user("Label_2", "Work/Projects", Some(BLUE), 230, 5),
+                user("Label_3", "Work/Projects/Apollo", Some(PURPLE), 96, 3),
+                user("Label_4", "Work/Meetings", None, 74, 0),
+                user("Label_5", "Personal", Some(GREEN), 301, 4),
+                user("Label_6", "Personal/Travel", Some(PINK), 45, 1),
+                user("Label_7", "Personal/Family", None, 120, 2),
+                user("Label_8", "Receipts", Some(YELLOW), 188, 0),
+                user("Label_9", "Newsletters", None, 640, 37),
+            ])),
+        }
+    }
+}
+
+impl Transport for Demo {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, Error>> {
+        Box::pin(async move { Ok(self.answer(&request)) })
+    }
+}
+
+impl Demo {
+    fn answer(&self, request: &HttpRequest) -> HttpResponse {
+        let path = request.url.path();
+        if request.method == Method::POST {
+            match path {
+                "/token" => return respond(StatusCode::OK, Some(token())),
+                "/device/code" => return respond(StatusCode::OK, Some(device_code())),
+                "/revoke" => return respond(StatusCode::OK, None),
+                _ => {}
+            }
+        }
+
+        let id = match path.split_once("/labels") {
+            Some((_, id)) => id.trim_start_matches('/'),
+            None => {
+                let status = StatusCode::NOT_FOUND;
+                return respond(status, Some(fake_gmail::error(status)));
+            }
+        };
+
+        let mut labels = self.labels.lock().unwrap_or_else(PoisonError::into_inner);
+        let (status, body) = labels.answer(request.method.as_str(), id, &request.body);
+        respond(status, body)
+    }
+}
+
+fn token() -> Value {
+    json!({
+        "access_token": "demo-access-token",
+        "refresh_token": "demo-refresh-token",
+        "token_type": "Bearer",
+        "expires_in": 3599,
+        "scope": "https://www.googleapis.com/auth/gmail.labels openid https://www.googleapis.com/auth/userinfo.email",
+    })
+}
+
+/// Approved before anyone could get to it, since the token endpoint takes
+/// anything. The code is still shown for the moment it takes.
+fn device_code() -> Value {
+    json!({
+        "device_code": "demo-device-code",
+        "user_code": "DEMO-CODE",
+        "verification_url": "https://www.google.com/device",
+        "expires_in": 1800,
+        "interval": 5,
+    })
+}
+
+fn respond(status_code: StatusCode, body: Option<Value>) -> HttpResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    HttpResponse {
+        status_code,
+        headers,
+        body: body.map(|body| body.to_string().into_bytes()).unwrap_or_default(),
+    }
+}
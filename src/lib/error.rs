@@ -1,28 +1,180 @@
-use std::sync::{Arc};
+use std::sync::Arc;
 
+use oauth2::{ErrorResponse, RequestTokenError};
+
+/// Everything that can go wrong in the library. Variants wrapping a foreign
+/// error hold it in an `Arc` so the whole enum stays `Clone`, which Iced
+/// needs for its messages.
+///
+/// Every variant maps to a process exit code through `exit_code`, so the CLI
+/// can tell a script *why* it failed, not just that it did.
 #[derive(Debug, Clone)]
 pub enum Error {
     PortPermissionDenied,
+    /// Something else is already listening on the loopback port.
+    PortInUse,
     InvalidPort,
     AuthServerClosedEarly,
-    PkceCodeVerifierLocked,
-    PkceCodeVerifierMissing,
-    KillSignalNotInitialized,
     MissingToken,
+    /// Someone cancelled the flow or request that was running.
+    Cancelled,
+    QuotaCostExceedsCapacity,
+    QuotaLimiterPoisoned,
+    /// The API rejected our access token. A fresh authorization is needed.
+    Unauthorized,
+    /// The user, or the server, turned the authorization down, e.g.
+    /// `access_denied`.
+    AuthorizationDenied(String),
+    /// The `state` in the redirect didn't match the one we sent.
+    StateMismatch,
+    /// The ID token failed a signature or claim check.
+    InvalidIdToken(String),
+    /// A missing or malformed configuration value, named in the message.
+    Configuration(String),
+    Database(Arc<rusqlite::Error>),
+    /// The network, or a non-success response from the API.
+    Http(Arc<reqwest::Error>),
+    /// The API answered, but not with a success. 401 is `Unauthorized`
+    /// instead.
+    HttpStatus(u16),
+    /// A response stopped arriving for longer than the read timeout.
+    TimedOut,
+    /// The authorization server refused the grant, e.g. `invalid_grant`.
+    OAuth(Arc<dyn std::error::Error + Send + Sync>),
+    Json(Arc<serde_json::Error>),
+    /// Valid JSON, but not the shape we expected.
+    UnexpectedResponse(String),
+    /// Nothing matched what was asked for, e.g. a label name. Named in the
+    /// message.
+    NotFound(String),
+    Io(Arc<std::io::Error>),
     Unknown(Arc<Box<dyn std::error::Error + Send + Sync>>),
 }
 
+/// CLI exit codes, one per failure category. These are listed in the CLI's
+/// `--help`, so treat them as a stable interface.
+pub mod exit_code {
+    pub const UNKNOWN: i32 = 1;
+    pub const CONFIGURATION: i32 = 3;
+    pub const DATABASE: i32 = 4;
+    pub const NETWORK: i32 = 5;
+    pub const NEEDS_REAUTH: i32 = 6;
+    pub const INVALID_RESPONSE: i32 = 7;
+    pub const LOCAL_LISTENER: i32 = 8;
+    pub const QUOTA: i32 = 9;
+    pub const NOT_FOUND: i32 = 10;
+    /// What a shell reports for a process stopped by Ctrl-C.
+    pub const CANCELLED: i32 = 130;
+}
+
+impl Error {
+    /// A short, stable name for the failure category, for machine-readable
+    /// output. It lines up one to one with `exit_code`.
+    pub fn kind(&self) -> &'static str {
+        match self.exit_code() {
+            exit_code::CONFIGURATION => "configuration",
+            exit_code::DATABASE => "database",
+            exit_code::NETWORK => "network",
+            exit_code::NEEDS_REAUTH => "needs_reauth",
+            exit_code::INVALID_RESPONSE => "invalid_response",
+            exit_code::LOCAL_LISTENER => "local_listener",
+            exit_code::QUOTA => "quota",
+            exit_code::NOT_FOUND => "not_found",
+            exit_code::CANCELLED => "cancelled",
+            _ => "unknown",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Configuration(_) => exit_code::CONFIGURATION,
+            Error::Database(_) => exit_code::DATABASE,
+            Error::Http(_) | Error::HttpStatus(_) | Error::TimedOut => exit_code::NETWORK,
+            Error::OAuth(_)
+            | Error::Unauthorized
+            | Error::MissingToken
+            | Error::AuthorizationDenied(_)
+            | Error::StateMismatch
+            | Error::InvalidIdToken(_) => exit_code::NEEDS_REAUTH,
+            Error::Json(_) | Error::UnexpectedResponse(_) => exit_code::INVALID_RESPONSE,
+            Error::PortPermissionDenied
+            | Error::PortInUse
+            | Error::InvalidPort
+            | Error::AuthServerClosedEarly => exit_code::LOCAL_LISTENER,
+            Error::QuotaCostExceedsCapacity | Error::QuotaLimiterPoisoned => exit_code::QUOTA,
+            Error::NotFound(_) => exit_code::NOT_FOUND,
+            Error::Cancelled => exit_code::CANCELLED,
+            Error::Io(_) | Error::Unknown(_) => exit_code::UNKNOWN,
+        }
+    }
+}
+
 impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
     fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
         Error::Unknown(Arc::new(error))
     }
 }
 
+/// Any file or socket. The loopback listener picks out its own failures to
+/// bind before they get here, since only it knows what they mean.
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
-        match error.kind() {
-            std::io::ErrorKind::PermissionDenied => return Error::PortPermissionDenied,
-            _ => error.into(),
+        Error::Io(Arc::new(error))
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Database(Arc::new(error))
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        match error.status() {
+            Some(reqwest::StatusCode::UNAUTHORIZED) => Error::Unauthorized,
+            _ => Error::Http(Arc::new(error)),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(Arc::new(error))
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        Error::InvalidIdToken(error.to_string())
+    }
+}
+
+impl From<oauth2::ConfigurationError> for Error {
+    fn from(error: oauth2::ConfigurationError) -> Self {
+        Error::Configuration(error.to_string())
+    }
+}
+
+impl From<oauth2::url::ParseError> for Error {
+    fn from(error: oauth2::url::ParseError) -> Self {
+        Error::Configuration(format!("Invalid URL: {}", error))
+    }
+}
+
+/// The token endpoint can fail in two very different ways: it can be
+/// unreachable, which is a network problem the transport has already turned
+/// into an `Error`, or it can answer with an OAuth error, which means the
+/// user has to authorize again. Generic over the error response so the
+/// device flow's errors convert the same way.
+impl<TE> From<RequestTokenError<Error, TE>> for Error
+where
+    TE: ErrorResponse + Send + Sync + 'static,
+{
+    fn from(error: RequestTokenError<Error, TE>) -> Self {
+        match error {
+            RequestTokenError::Request(error) => error,
+            error => Error::OAuth(Arc::new(error)),
         }
     }
 }
@@ -36,14 +188,45 @@ impl std::fmt::Display for Error {
             Error::AuthServerClosedEarly => {
                 write!(f, "Auth server closed before receiving auth code.")
             }
-            Error::PkceCodeVerifierLocked => write!(f, "Pkce code verifier is locked."),
-            Error::PkceCodeVerifierMissing => write!(f, "Pkce code verifier is missing."),
+            Error::PortInUse => write!(f, "The local port is already in use."),
             Error::InvalidPort => write!(f, "Invalid port number."),
-            Error::KillSignalNotInitialized => write!(f, "Kill signal not initialized."),
             Error::MissingToken => write!(f, "Missing token."),
+            Error::Cancelled => write!(f, "Cancelled."),
+            Error::QuotaCostExceedsCapacity => {
+                write!(f, "Quota cost is larger than the limiter can ever hold.")
+            }
+            Error::QuotaLimiterPoisoned => write!(f, "Quota limiter is poisoned."),
+            Error::Unauthorized => write!(f, "Access token rejected, please authorize again."),
+            Error::AuthorizationDenied(reason) => write!(f, "Authorization denied: {}", reason),
+            Error::StateMismatch => {
+                write!(f, "Authorization response state does not match the request.")
+            }
+            Error::InvalidIdToken(reason) => write!(f, "Invalid ID token: {}", reason),
+            Error::Configuration(message) => write!(f, "Configuration error: {}", message),
+            Error::Database(_) => write!(f, "Database error."),
+            Error::Http(_) => write!(f, "HTTP request failed."),
+            Error::HttpStatus(status) => write!(f, "HTTP request failed with status {}.", status),
+            Error::TimedOut => write!(f, "Timed out waiting for the server to respond."),
+            Error::OAuth(_) => write!(f, "Authorization server rejected the request."),
+            Error::Json(_) => write!(f, "Could not parse JSON response."),
+            Error::UnexpectedResponse(message) => write!(f, "Unexpected response: {}", message),
+            Error::NotFound(message) => write!(f, "Not found: {}", message),
+            Error::Io(error) => write!(f, "I/O error: {}", error),
             Error::Unknown(error) => write!(f, "Unknown error: {:?}", error),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Database(error) => Some(error.as_ref()),
+            Error::Http(error) => Some(error.as_ref()),
+            Error::OAuth(error) => Some(error.as_ref()),
+            Error::Json(error) => Some(error.as_ref()),
+            Error::Io(error) => Some(error.as_ref()),
+            Error::Unknown(error) => Some(error.as_ref().as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Milestones in the auth and fetch pipeline, as they happen. Purely
+/// informational; nothing waits on whoever is listening.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The authorization URL is ready to be opened.
+    UrlReady { url: String },
+    /// The loopback listener is waiting for the browser.
+    ListenerBound { port: u16 },
+    /// The browser came back with a code, or one was pasted in.
+    CodeReceived,
+    /// A code, or an approved device code, was traded for a token.
+    TokenExchanged,
+    /// An expired access token was swapped for a new one.
+    RefreshPerformed,
+    LabelsFetched { count: usize },
+    LabelsStored { count: usize },
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UrlReady { url } => write!(f, "Authorization URL ready: {}", url),
+            Self::ListenerBound { port } => write!(f, "Listening for the browser on port {}", port),
+            Self::CodeReceived => write!(f, "Authorization code received"),
+            Self::TokenExchanged => write!(f, "Token exchanged"),
+            Self::RefreshPerformed => write!(f, "Access token refreshed"),
+            Self::LabelsFetched { count } => write!(f, "Fetched {} labels", count),
+            Self::LabelsStored { count } => write!(f, "Stored {} labels", count),
+        }
+    }
+}
+
+/// Anything that wants to hear about `Event`s. Closures work, and so does
+/// the sending half of a channel, which is the easy way to get them out of
+/// a future running somewhere else.
+pub trait Observer: Send + Sync {
+    fn notify(&self, event: &Event);
+}
+
+impl<F> Observer for F
+where
+    F: Fn(&Event) + Send + Sync,
+{
+    fn notify(&self, event: &Event) {
+        self(event)
+    }
+}
+
+// Nobody listening any more isn't the sender's problem.
+impl Observer for UnboundedSender<Event> {
+    fn notify(&self, event: &Event) {
+        let _ = self.send(event.clone());
+    }
+}
+
+/// The observer a request reports to, if any. Shared between clones.
+#[derive(Clone, Default)]
+pub struct Events(Option<Arc<dyn Observer>>);
+
+impl Events {
+    pub fn new(observer: Arc<dyn Observer>) -> Self {
+        Self(Some(observer))
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(observer) = &self.0 {
+            observer.notify(&event);
+        }
+    }
+}
+
+impl Debug for Events {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Events")
+            .field(&self.0.as_ref().map(|_| "observer"))
+            .finish()
+    }
+}
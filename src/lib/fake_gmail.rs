@@ -0,0 +1,109 @@
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+/// Gmail's labels API, faked over a list of labels, for the demo and the
+/// mock server alike. Labels created or deleted stick around for as long as
+/// it does. New ids come from a counter, like Gmail's, so one that's been
+/// deleted is never handed out again.
+#[derive(Debug)]
+pub(crate) struct Labels {
+    labels: Vec<Value>,
+    next_id: usize,
+}
+
+impl Labels {
+    pub(crate) fn new(labels: Vec<Value>) -> Self {
+        let highest = labels
+            .iter()
+            .filter_map(|label| label["id"].as_str()?.strip_prefix("Label_")?.parse().ok())
+            .max()
+            .unwrap_or(0);
+
+        Self { labels, next_id: highest + 1 }
+    }
+
+    /// Answer a call for the label `id`, or for the whole list when `id` is
+    /// empty.
+    pub(crate) fn answer(
+        &mut self,
+        method: &str,
+        id: &str,
+        body: &[u8],
+    ) -> (StatusCode, Option<Value>) {
+        let position = self.labels.iter().position(|label| label["id"] == id);
+        match (method, id, position) {
+            // labels.list leaves the counts out, same as the real thing.
+            ("GET", "", _) => {
+                let listed: Vec<Value> = self.labels.iter().map(without_counts).collect();
+                (StatusCode::OK, Some(json!({ "labels": listed })))
+            }
+            ("GET", _, Some(position)) => (StatusCode::OK, Some(self.labels[position].clone())),
+            ("POST", "", _) => {
+                let name = serde_json::from_slice::<Value>(body)
+                    .ok()
+                    .and_then(|body| body["name"].as_str().map(str::to_owned));
+                let name = match name {
+                    Some(name) => name,
+                    None => return (StatusCode::BAD_REQUEST, Some(error(StatusCode::BAD_REQUEST))),
+                };
+                let created = label(&format!("Label_{}", self.next_id), &name, "user", None, 0, 0);
+                self.next_id += 1;
+                self.labels.push(created.clone());
+                (StatusCode::OK, Some(created))
+            }
+            ("DELETE", _, Some(position)) => {
+                self.labels.remove(position);
+                (StatusCode::NO_CONTENT, None)
+            }
+            _ => (StatusCode::NOT_FOUND, Some(error(StatusCode::NOT_FOUND))),
+        }
+    }
+}
+
+/// A label the way labels.get sends it, with a color as a text and
+/// background pair.
+pub(crate) fn label(
+    id: &str,
+    name: &str,
+    label_type: &str,
+    color: Option<(&str, &str)>,
+    total: u32,
+    unread: u32,
+) -> Value {
+    let mut label = json!({
+        "id": id,
+        "name": name,
+        "type": label_type,
+        "messageListVisibility": "show",
+        "labelListVisibility": "labelShow",
+        "messagesTotal": total,
+        "messagesUnread": unread,
+        "threadsTotal": total * 3 / 4,
+        "threadsUnread": unread,
+    });
+    if let Some((text, background)) = color {
+        label["color"] = json!({ "textColor": text, "backgroundColor": background });
+    }
+    label
+}
+
+/// An error body the way Google's APIs write them.
+pub(crate) fn error(status: StatusCode) -> Value {
+    let name = match status {
+        StatusCode::BAD_REQUEST => "INVALID_ARGUMENT",
+        StatusCode::UNAUTHORIZED => "UNAUTHENTICATED",
+        StatusCode::NOT_FOUND => "NOT_FOUND",
+        StatusCode::TOO_MANY_REQUESTS => "RESOURCE_EXHAUSTED",
+        _ => "UNKNOWN",
+    };
+    let message = status.canonical_reason().unwrap_or(name);
+    json!({ "error": { "code": status.as_u16(), "message": message, "status": name } })
+}
+
+fn without_counts(label: &Value) -> Value {
+    let mut label = label.clone();
+    if let Some(fields) = label.as_object_mut() {
+        fields.retain(|name, _| !name.ends_with("Total") && !name.ends_with("Unread"));
+    }
+    label
+}
@@ -0,0 +1,191 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use reqwest::Url;
+use rusqlite::Connection;
+
+use crate::{
+    configuration::{discovery, Configuration},
+    db,
+    error::{exit_code, Error},
+    event::{Event, Events},
+    id_token,
+    label::Label,
+    process,
+    request::{GmailLabelRequest, Unauthorized},
+    token::Token,
+    transport::{ReqwestTransport, Transport},
+};
+
+/// The whole trip from a configuration to a list of labels, for anything
+/// embedding the crate that doesn't care how it gets there. A stored token
+/// is used if there is one, refreshed if it has to be, and only when
+/// neither works is `open_url` handed the authorization URL to send the
+/// user to. The new token and the labels are saved to `db` along the way.
+///
+/// Both binaries need more control than this, so they use the pieces below
+/// instead, but it's the same path.
+pub async fn labels(
+    config: &Configuration,
+    db: &Connection,
+    open_url: impl FnOnce(&Url),
+) -> Result<Vec<Label>, Error> {
+    let missing = config.api.missing_fields();
+    if !missing.is_empty() {
+        let names: Vec<String> = missing.iter().map(ToString::to_string).collect();
+        return Err(Error::Configuration(format!("Missing {}", names.join(", "))));
+    }
+
+    // One client for discovery, the token and the API alike.
+    let transport: Arc<dyn Transport> =
+        Arc::new(ReqwestTransport::from_configuration(&config.http)?);
+    let config = match config.api.issuer_url.as_deref() {
+        Some(issuer) => {
+            let discovered = discovery::discover(issuer, db, false, transport.clone()).await?;
+            discovered.metadata.apply(config.clone())
+        }
+        None => config.clone(),
+    };
+
+    let request = GmailLabelRequest::new(&config, transport)?;
+    let request = authorized_request(request, &config.profile, db, |request| {
+        browser_login(request, open_url)
+    })
+    .await?;
+
+    store_labels(request, false, &config.profile, db).await
+}
+
+/// Turn `unauthorized` into a request that is ready to call the API: with
+/// the profile's stored token if it's still good, refreshed if it has
+/// expired, or whatever `login` comes back with otherwise. A new token has
+/// its ID token checked and is saved before returning.
+pub async fn authorized_request<L, F>(
+    unauthorized: GmailLabelRequest<Unauthorized>,
+    profile: &str,
+    db: &Connection,
+    login: L,
+) -> Result<GmailLabelRequest, Error>
+where
+    L: FnOnce(GmailLabelRequest<Unauthorized>) -> F,
+    F: Future<Output = Result<GmailLabelRequest, Error>>,
+{
+    let stored = Token::load(db, profile)?;
+    let reused = matches!(&stored, Some(token) if !token.is_expired());
+
+    let request = authorize(unauthorized, stored, login).await?;
+    if reused {
+        return Ok(request);
+    }
+    let request = verify_identity(request, db).await?;
+
+    request.token().save(db, profile)?;
+
+    Ok(request)
+}
+
+/// The part of `authorized_request` that doesn't touch the database, given
+/// the stored token up front. A refresh the server turns down falls back to
+/// `login`, the same as having no token at all. The GUI's futures have to be
+/// `Send`, so they can't borrow a connection, and this is what they run
+/// instead. Checking and saving a new token is left to the caller.
+pub async fn authorize<L, F>(
+    unauthorized: GmailLabelRequest<Unauthorized>,
+    stored: Option<Token>,
+    login: L,
+) -> Result<GmailLabelRequest, Error>
+where
+    L: FnOnce(GmailLabelRequest<Unauthorized>) -> F,
+    F: Future<Output = Result<GmailLabelRequest, Error>>,
+{
+    match stored {
+        Some(token) if !token.is_expired() => Ok(unauthorized.with_token(token)),
+        Some(token) if token.refresh_token.is_some() => {
+            // A refresh token that's been revoked, or has simply lapsed, is
+            // no reason to give up. Anything else, like the network being
+            // down, is.
+            match unauthorized.clone().with_token(token).oauth2_token_refresh().await {
+                Err(error) if error.exit_code() == exit_code::NEEDS_REAUTH => {
+                    login(unauthorized).await
+                }
+                result => result,
+            }
+        }
+        _ => login(unauthorized).await,
+    }
+}
+
+/// The loopback flow: hand the URL over, then wait for the browser to come
+/// back with a code.
+pub async fn browser_login(
+    request: GmailLabelRequest<Unauthorized>,
+    open_url: impl FnOnce(&Url),
+) -> Result<GmailLabelRequest, Error> {
+    let request = request.authorize();
+    open_url(request.auth_url());
+    request.receive_callback().await
+}
+
+/// Check any ID token that came back with the last token response, fetching
+/// the provider's keys if the cached ones won't do.
+pub async fn verify_identity(
+    request: GmailLabelRequest,
+    db: &Connection,
+) -> Result<GmailLabelRequest, Error> {
+    let raw = request.token().id_token.clone();
+    let (raw, url) = match (raw, request.jwks_url()) {
+        (Some(raw), Some(url)) => (raw, url.to_string()),
+        _ => return Ok(request),
+    };
+
+    let keys = id_token::key_set_for(&raw, &url, db, request.transport()).await?;
+    request.verify_identity(&keys)
+}
+
+/// Fetch the labels and replace the profile's saved set with them. With
+/// `details`, each label is fetched on its own to fill in its counts. The
+/// replacement is one transaction, so a failed or cancelled fetch leaves the
+/// old set in place.
+pub async fn store_labels(
+    request: GmailLabelRequest,
+    details: bool,
+    profile: &str,
+    db: &Connection,
+) -> Result<Vec<Label>, Error> {
+    let (raw_list, request) = request.get_labels().await?;
+
+    if !details {
+        return save_labels(&raw_list, profile, db, request.events());
+    }
+
+    let transaction = db.unchecked_transaction()?;
+    db::delete_labels(profile, &transaction)?;
+    for id in process::label_ids(&raw_list)? {
+        let (raw_label, _) = request.clone().get_label(&id).await?;
+        process::from_label_json_str(&raw_label, profile, &transaction)?;
+    }
+
+    let labels = db::get_full_labels(profile, &transaction)?;
+    transaction.commit()?;
+    request.events().emit(Event::LabelsStored { count: labels.len() });
+    Ok(labels)
+}
+
+/// Replace the profile's saved labels with a labels.list response. For
+/// callers that did the fetching themselves.
+pub fn save_labels(
+    raw_list: &str,
+    profile: &str,
+    db: &Connection,
+    events: &Events,
+) -> Result<Vec<Label>, Error> {
+    let transaction = db.unchecked_transaction()?;
+    db::delete_labels(profile, &transaction)?;
+    process::from_json_str(raw_list, profile, &transaction)?;
+
+    // This is pulled from the database instead of directly... because I wanted to use the database.
+    let labels = db::get_full_labels(profile, &transaction)?;
+    transaction.commit()?;
+    events.emit(Event::LabelsStored { count: labels.len() });
+    Ok(labels)
+}
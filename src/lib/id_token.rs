@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use rusqlite::Connection;
+use serde::Deserialize;
+
+use crate::db;
+use crate::error::Error;
+use crate::token::now;
+use crate::transport::{self, Transport};
+
+/// Providers rotate their signing keys every few days, and publish the new
+/// ones well ahead of use. An unknown key id forces a refetch anyway.
+pub const MAX_AGE: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Everything needed to check an ID token besides the token itself.
+#[derive(Debug, Clone)]
+pub struct Expected {
+    /// Any of these is accepted, since Google signs with the issuer both
+    /// with and without its scheme.
+    pub issuers: Vec<String>,
+    pub client_id: String,
+    /// Only the code flow sends one. Device and refresh grants don't.
+    pub nonce: Option<String>,
+}
+
+/// Who the token belongs to, once the ID token has checked out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    email: Option<String>,
+    nonce: Option<String>,
+}
+
+/// Check the signature against the key set, then `iss`, `aud`, `exp` and the
+/// nonce. The key set is passed in rather than fetched so a test can sign
+/// with its own keys.
+pub fn validate(id_token: &str, keys: &JwkSet, expected: &Expected) -> Result<Identity, Error> {
+    let header = decode_header(id_token)?;
+
+    // Without a kid the only safe guess is a set with just the one key.
+    let jwk = match (&header.kid, keys.keys.as_slice()) {
+        (Some(kid), _) => keys.find(kid),
+        (None, [only]) => Some(only),
+        (None, _) => None,
+    }
+    .ok_or_else(|| Error::InvalidIdToken("signed with an unknown key".to_string()))?;
+
+    // The key decides the algorithm, not the token. Otherwise a forged
+    // header could have a public key used as an HMAC secret, say.
+    let algorithm = algorithm_for(jwk)?;
+    if header.alg != algorithm {
+        return Err(Error::InvalidIdToken(format!(
+            "signed with {:?}, but the key is for {:?}",
+            header.alg, algorithm
+        )));
+    }
+
+    let mut validation = Validation::new(algorithm);
+    validation.set_issuer(&expected.issuers);
+    validation.set_audience(&[&expected.client_id]);
+
+    let claims = decode::<Claims>(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+
+    if expected.nonce.is_some() && claims.nonce != expected.nonce {
+        return Err(Error::InvalidIdToken("nonce mismatch".to_string()));
+    }
+
+    Ok(Identity { subject: claims.sub, email: claims.email })
+}
+
+/// The key's own `alg`, or the usual one for its type when it doesn't say.
+/// Symmetric keys are never accepted, a provider has no business publishing
+/// one.
+fn algorithm_for(jwk: &Jwk) -> Result<Algorithm, Error> {
+    let algorithm = match (jwk.common.algorithm, &jwk.algorithm) {
+        (_, AlgorithmParameters::OctetKey(_)) => None,
+        (Some(algorithm), _) => Some(algorithm),
+        (None, AlgorithmParameters::RSA(_)) => Some(Algorithm::RS256),
+        (None, AlgorithmParameters::EllipticCurve(params)) => match params.curve {
+            EllipticCurve::P256 => Some(Algorithm::ES256),
+            EllipticCurve::P384 => Some(Algorithm::ES384),
+            _ => None,
+        },
+        (None, AlgorithmParameters::OctetKeyPair(_)) => Some(Algorithm::EdDSA),
+    };
+
+    match algorithm {
+        Some(Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) | None => {
+            Err(Error::InvalidIdToken("the signing key isn't usable".to_string()))
+        }
+        Some(algorithm) => Ok(algorithm),
+    }
+}
+
+/// Whether the token names a key the set doesn't have, meaning the cached
+/// set is out of date.
+pub fn needs_refresh(id_token: &str, keys: &JwkSet) -> Result<bool, Error> {
+    let header = decode_header(id_token)?;
+    Ok(matches!(header.kid, Some(kid) if keys.find(&kid).is_none()))
+}
+
+/// The cached key set for a JWKS URL, and whether it's within `MAX_AGE`.
+pub fn cached(url: &str, db: &Connection) -> Result<Option<(JwkSet, bool)>, Error> {
+    let (document, fetched_at) = match db::get_jwks(url, db)? {
+        Some(cached) => cached,
+        None => return Ok(None),
+    };
+    let fresh = now().saturating_sub(fetched_at) < MAX_AGE.as_secs();
+
+    Ok(Some((serde_json::from_str(&document)?, fresh)))
+}
+
+/// Fetch a key set, ignoring the cache. The raw document comes back too,
+/// for `store`.
+pub async fn fetch(url: String, transport: Arc<dyn Transport>) -> Result<(JwkSet, String), Error> {
+    let raw = transport::get(transport.as_ref(), &url).await?;
+
+    Ok((serde_json::from_str(&raw)?, raw))
+}
+
+pub fn store(url: &str, raw: &str, db: &Connection) -> Result<(), Error> {
+    db::save_jwks(url, raw, now(), db)
+}
+
+/// A key set that can check this token: the cached one if it's fresh and
+/// has the token's key, otherwise a new one.
+pub async fn key_set_for(
+    id_token: &str,
+    url: &str,
+    db: &Connection,
+    transport: Arc<dyn Transport>,
+) -> Result<JwkSet, Error> {
+    if let Some((keys, true)) = cached(url, db)? {
+        if !needs_refresh(id_token, &keys)? {
+            return Ok(keys);
+        }
+    }
+
+    let (keys, raw) = fetch(url.to_string(), transport).await?;
+    store(url, &raw, db)?;
+
+    Ok(keys)
+}
@@ -13,5 +13,13 @@ pub struct Label {
     pub messages_unread: Option<isize>,
     pub threads_total: Option<isize>,
     pub threads_unread: Option<isize>,
-    pub color: Option<String>,
+    pub color: Option<LabelColor>,
+}
+
+/// Gmail sends colors as a pair of hex strings rather than a single value.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LabelColor {
+    pub text_color: Option<String>,
+    pub background_color: Option<String>,
 }
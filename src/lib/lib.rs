@@ -1,7 +1,20 @@
+pub mod callback;
+pub mod cassette;
 pub mod configuration;
 pub mod db;
+pub mod demo;
 pub mod error;
-mod label;
+pub mod event;
+mod fake_gmail;
+pub mod fetch;
+pub mod id_token;
+pub mod label;
 mod localhost_oauth_server;
 pub mod process;
+pub mod provider;
+pub mod quota;
 pub mod request;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+pub mod token;
+pub mod transport;
@@ -1,8 +1,10 @@
+use crate::callback;
 use crate::configuration::port::Port;
 use crate::error::Error;
+use crate::event::{Event, Events};
 use std::io::{Read, Write};
 use std::net::{SocketAddr, TcpListener, TcpStream};
-use tokio::sync::mpsc::{error::TryRecvError, Receiver};
+use tokio_util::sync::CancellationToken;
 use tokio::time::{sleep, Duration};
 
 const POLL_DELAY_NS: u32 = 50_000u32;
@@ -21,34 +23,32 @@ const POLL_DELAY_NS: u32 = 50_000u32;
 /// here also I went with a slightly less pragmatic, lower level, method. This
 /// is a showpiece after all.
 ///
-/// My one concession is using tokio to run the sleep and mpsc. No sense in 
+/// My one concession is using tokio to run the sleep and cancellation. No sense in 
 /// getting sloppy with resources just because we're indulging in primitive 
 /// code.
+///
+/// Stray requests, like a browser asking for favicon.ico, or a redirect with
+/// the wrong state, are answered and ignored. An explicit refusal from the
+/// authorization server ends the wait, since nothing else is coming.
 pub async fn raw_tcp_listener(
-    port: Port, mut signal: Receiver<()>
+    port: Port, expected_state: String, cancel: &CancellationToken, events: &Events
 ) -> Result<String, Error> {
     let address = SocketAddr::from(([127, 0, 0, 1], port.as_u16()));
-    let listener: TcpListener = TcpListener::bind(address)?;
+    let listener: TcpListener = TcpListener::bind(address).map_err(bind_error)?;
     listener.set_nonblocking(true)?;
+    events.emit(Event::ListenerBound { port: port.as_u16() });
     for maybe_stream in listener.incoming() {
         match maybe_stream {
-            Ok(stream) => {
-                if let Ok(code) = collect_stream(stream) {
-                    return Ok(code);
-                };
-            }
+            Ok(stream) => match collect_stream(stream, &expected_state) {
+                Ok(code) => return Ok(code),
+                Err(err @ Error::AuthorizationDenied(_)) => return Err(err),
+                Err(_) => {}
+            },
             Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
-                match signal.try_recv() {
-                    Ok(_) => {
-                        return Err(Error::AuthServerClosedEarly);
-                    }
-                    Err(err) if err == TryRecvError::Empty => {
-                        sleep(Duration::new(0, POLL_DELAY_NS)).await;
-                    }
-                    Err(_) => {
-                        return Err(Error::AuthServerClosedEarly);
-                    }
+                if cancel.is_cancelled() {
+                    return Err(Error::Cancelled);
                 }
+                sleep(Duration::new(0, POLL_DELAY_NS)).await;
             }
             Err(_) => {
                 return Err(Error::AuthServerClosedEarly);
@@ -59,40 +59,33 @@ pub async fn raw_tcp_listener(
     Err(Error::AuthServerClosedEarly)
 }
 
-fn collect_stream(mut stream: TcpStream) -> Result<String, Error> {
+/// Low ports need admin rights, and a port can only be had by one listener.
+fn bind_error(error: std::io::Error) -> Error {
+    match error.kind() {
+        std::io::ErrorKind::PermissionDenied => Error::PortPermissionDenied,
+        std::io::ErrorKind::AddrInUse => Error::PortInUse,
+        _ => error.into(),
+    }
+}
+
+fn collect_stream(mut stream: TcpStream, expected_state: &str) -> Result<String, Error> {
     // The odds of a request url being larger than 2kb is slim.
     // This would be risky in production, but we're just going
     // to pull in up to 3kib and process them as a string.
     let mut buffer = [0; 3072];
-    stream.read(&mut buffer)?;
-    let raw_request = String::from_utf8_lossy(&buffer[..]);
+    let read = stream.read(&mut buffer)?;
+    let raw_request = String::from_utf8_lossy(&buffer[..read]);
 
-    let code = extract_code(raw_request.to_string());
+    let code = callback::request_target(&raw_request)
+        .ok_or(Error::MissingToken)
+        .and_then(|target| callback::parse_redirect(target, expected_state));
 
-    if code.is_some() {
-        let ok = "HTTP/1.1 200 OK\r\n\r\n200 OK".as_bytes();
-        stream
-            .write(ok)
-            .unwrap();
-    } else {
-        let unauthorized = "HTTP/1.1 401 Unauthorized\r\n\r\n401 Unauthorized"
-            .as_bytes();
-        stream
-            .write(unauthorized)
-            .unwrap();
-    }
-
-    code.ok_or(Error::MissingToken)
-}
+    let response = match code {
+        Ok(_) => "HTTP/1.1 200 OK\r\n\r\nAuthorized, you can close this window.",
+        Err(Error::AuthorizationDenied(_)) => "HTTP/1.1 403 Forbidden\r\n\r\nAuthorization denied.",
+        Err(_) => "HTTP/1.1 401 Unauthorized\r\n\r\n401 Unauthorized",
+    };
+    stream.write_all(response.as_bytes())?;
 
-fn extract_code(raw_request: String) -> Option<String> {
-    let code_and_tail = raw_request.rsplit_once("code=")?.1;
-    let code_and_url = code_and_tail.rsplit_once(" HTTP/1.1")?.0;
-    let code = code_and_url.split('&').next()?.to_string();
-
-    if code.len() > 0 {
-        Some(code)
-    } else {
-        None
-    }
+    code
 }
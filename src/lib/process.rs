@@ -2,34 +2,82 @@ use postcard::to_stdvec;
 use rusqlite::{params, Connection};
 use serde_json::Value;
 
+use crate::error::Error;
+
 /// This is honestly just silly. The whole reason I'm doing this is 
 /// to artificially include serde and database functionality.
-pub fn from_json_str(raw_json: &str, db: &Connection) {
-    let values: Value = serde_json::from_str(raw_json).unwrap();
+pub fn from_json_str(raw_json: &str, profile: &str, db: &Connection) -> Result<(), Error> {
+    let values: Value = serde_json::from_str(raw_json)?;
 
+    // An account with no user labels still has system labels, so a missing
+    // array means this wasn't a labels.list response at all.
     values
         .get("labels")
-        .unwrap()
-        .as_array()
-        .unwrap()
+        .and_then(Value::as_array)
+        .ok_or_else(|| Error::UnexpectedResponse("no labels array".to_string()))?
         .iter()
-        .for_each(save(db))
+        .try_for_each(save(profile, db))
+}
+
+/// Pull just the label ids out of a labels.list response, so each one can
+/// be fetched in full with labels.get.
+pub fn label_ids(raw_json: &str) -> Result<Vec<String>, Error> {
+    let values: Value = serde_json::from_str(raw_json)?;
+
+    let ids = values
+        .get("labels")
+        .and_then(Value::as_array)
+        .map(|labels| {
+            labels
+                .iter()
+                .filter_map(|label| label.get("id")?.as_str().map(str::to_owned))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(ids)
 }
 
-fn save(db: &Connection) -> impl FnMut(&Value) + '_ {
-    |label: &Value| {
+/// Find a label in a labels.list response by id, falling back to its name.
+pub fn find_label_id(raw_json: &str, id_or_name: &str) -> Result<Option<String>, Error> {
+    let values: Value = serde_json::from_str(raw_json)?;
+    let labels = values
+        .get("labels")
+        .and_then(Value::as_array)
+        .ok_or_else(|| Error::UnexpectedResponse("no labels array".to_string()))?;
+
+    let field_matches = |field: &str| {
+        labels
+            .iter()
+            .find(|label| label.get(field).and_then(Value::as_str) == Some(id_or_name))
+            .and_then(|label| label.get("id")?.as_str().map(str::to_owned))
+    };
+
+    Ok(field_matches("id").or_else(|| field_matches("name")))
+}
+
+/// Save a single labels.get response.
+pub fn from_label_json_str(raw_json: &str, profile: &str, db: &Connection) -> Result<(), Error> {
+    let value: Value = serde_json::from_str(raw_json)?;
+
+    save(profile, db)(&value)
+}
+
+fn save<'a>(profile: &'a str, db: &'a Connection) -> impl FnMut(&Value) -> Result<(), Error> + 'a {
+    move |label: &Value| {
         let raw_label: Option<crate::label::Label> = serde_json::from_value(label.clone()).ok();
 
-        match raw_label {
-            Some(label) => {
-                let post_label = to_stdvec(&label).unwrap();
-                db.execute(
-                    "INSERT INTO labels (name, postcard) VALUES (?, ?)",
-                    params![label.name, post_label],
-                )
-                .expect("Failed to insert label");
-            }
-            None => {}
+        if let Some(label) = raw_label {
+            let post_label = to_stdvec(&label).map_err(Box::from)?;
+            // Labels are attributed to whichever account the profile's
+            // token belongs to, if its ID token told us.
+            db.execute(
+                "INSERT INTO labels (profile, account, name, postcard)
+                 VALUES (?1, (SELECT email FROM tokens WHERE profile = ?1), ?2, ?3)",
+                params![profile, label.name, post_label],
+            )?;
         }
+
+        Ok(())
     }
 }
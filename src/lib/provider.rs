@@ -0,0 +1,199 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
+use crate::configuration::Configuration;
+
+const GOOGLE_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/auth";
+const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const GOOGLE_DEVICE_AUTH_URL: &str = "https://oauth2.googleapis.com/device/code";
+const GOOGLE_REVOCATION_URL: &str = "https://oauth2.googleapis.com/revoke";
+const GMAIL_LABELS_URL: &str = "https://www.googleapis.com/gmail/v1/users/me/labels";
+const GMAIL_LABELS_SCOPE: &str = "https://www.googleapis.com/auth/gmail.labels";
+const GOOGLE_EMAIL_SCOPE: &str = "https://www.googleapis.com/auth/userinfo.email";
+const GOOGLE_PROFILE_SCOPE: &str = "https://www.googleapis.com/auth/userinfo.profile";
+const GOOGLE_ISSUER: &str = "https://accounts.google.com";
+const GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+
+/// Everything about an OAuth API that the PKCE and loopback machinery in
+/// `request` doesn't care about. The endpoints here are only defaults; any
+/// that are set in the `Configuration` win.
+pub trait Provider: Debug + Send + Sync {
+    fn auth_url(&self) -> Option<String>;
+    fn token_url(&self) -> Option<String>;
+    fn device_auth_url(&self) -> Option<String> {
+        None
+    }
+    fn revocation_url(&self) -> Option<String> {
+        None
+    }
+    /// Who signs the ID tokens, and where their keys are. Without both, ID
+    /// tokens can't be checked, and the account stays unknown.
+    fn issuer(&self) -> Option<String> {
+        None
+    }
+    fn jwks_url(&self) -> Option<String> {
+        None
+    }
+    /// What to ask for when authorizing.
+    fn scopes(&self) -> Vec<String>;
+    /// Whether a token response's scopes cover a requested one. Servers may
+    /// report a scope under another name than the one asked for.
+    fn grants(&self, requested: &str, granted: &[String]) -> bool {
+        granted.iter().any(|scope| scope == requested)
+    }
+    /// Provider specific parameters for the authorization URL.
+    fn extra_auth_params(&self) -> Vec<(&'static str, &'static str)> {
+        Vec::new()
+    }
+    /// The collection `get_labels` lists, and the base of the single item
+    /// calls.
+    fn resource_url(&self) -> Option<String>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Google;
+
+impl Provider for Google {
+    fn auth_url(&self) -> Option<String> {
+        Some(GOOGLE_AUTH_URL.to_string())
+    }
+
+    fn token_url(&self) -> Option<String> {
+        Some(GOOGLE_TOKEN_URL.to_string())
+    }
+
+    fn device_auth_url(&self) -> Option<String> {
+        Some(GOOGLE_DEVICE_AUTH_URL.to_string())
+    }
+
+    fn revocation_url(&self) -> Option<String> {
+        Some(GOOGLE_REVOCATION_URL.to_string())
+    }
+
+    fn issuer(&self) -> Option<String> {
+        Some(GOOGLE_ISSUER.to_string())
+    }
+
+    fn jwks_url(&self) -> Option<String> {
+        Some(GOOGLE_JWKS_URL.to_string())
+    }
+
+    // openid and email get us an ID token saying whose labels these are.
+    fn scopes(&self) -> Vec<String> {
+        [GMAIL_LABELS_SCOPE, "openid", "email"].map(str::to_owned).to_vec()
+    }
+
+    // Google answers the OpenID shorthands with its own scope URLs.
+    fn grants(&self, requested: &str, granted: &[String]) -> bool {
+        let alias = match requested {
+            "email" => Some(GOOGLE_EMAIL_SCOPE),
+            "profile" => Some(GOOGLE_PROFILE_SCOPE),
+            _ => None,
+        };
+        granted
+            .iter()
+            .any(|scope| scope == requested || Some(scope.as_str()) == alias)
+    }
+
+    // Without offline access Google won't hand out a refresh token, and
+    // every run would need the browser again.
+    fn extra_auth_params(&self) -> Vec<(&'static str, &'static str)> {
+        vec![("access_type", "offline")]
+    }
+
+    fn resource_url(&self) -> Option<String> {
+        Some(GMAIL_LABELS_URL.to_string())
+    }
+}
+
+/// Any other OAuth API. There's nothing to default, so the endpoints all
+/// come from the `Configuration`.
+#[derive(Debug, Clone, Default)]
+pub struct Custom {
+    pub scopes: Vec<String>,
+    pub resource_url: Option<String>,
+}
+
+impl Provider for Custom {
+    fn auth_url(&self) -> Option<String> {
+        None
+    }
+
+    fn token_url(&self) -> Option<String> {
+        None
+    }
+
+    fn scopes(&self) -> Vec<String> {
+        self.scopes.clone()
+    }
+
+    fn resource_url(&self) -> Option<String> {
+        self.resource_url.clone()
+    }
+}
+
+/// Which `Provider` a profile uses, as it's stored in the database.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ProviderKind {
+    #[default]
+    Google,
+    Custom,
+}
+
+impl ProviderKind {
+    pub const ALL: [ProviderKind; 2] = [ProviderKind::Google, ProviderKind::Custom];
+}
+
+/// Build the provider a configuration asks for.
+pub fn from_configuration(config: &Configuration) -> Arc<dyn Provider> {
+    match config.api.provider {
+        ProviderKind::Google => Arc::new(Google),
+        ProviderKind::Custom => Arc::new(Custom {
+            scopes: config
+                .api
+                .scopes
+                .as_deref()
+                .map(|scopes| scopes.split_whitespace().map(str::to_owned).collect())
+                .unwrap_or_default(),
+            resource_url: config.api.resource_url.clone(),
+        }),
+    }
+}
+
+impl Display for ProviderKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Google => write!(f, "google"),
+            Self::Custom => write!(f, "custom"),
+        }
+    }
+}
+
+impl FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| format!("Unknown provider: {}", s))
+    }
+}
+
+impl ToSql for ProviderKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for ProviderKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|err: String| FromSqlError::Other(err.into()))
+    }
+}
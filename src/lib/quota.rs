@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tokio::time::{sleep, Duration};
+
+use crate::error::Error;
+
+/// Gmail allows 250 quota units per user per second, as a moving average.
+/// The bucket is sized so that a full second's allowance can be spent in a
+/// burst, after which calls are paced at the refill rate.
+const DEFAULT_CAPACITY: u32 = 250;
+const DEFAULT_REFILL_PER_SECOND: u32 = 250;
+
+/// The Gmail API methods this crate calls. Each one carries its own unit
+/// cost, which is looked up in `QuotaCosts` rather than hard-coded here.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum GmailMethod {
+    LabelsList,
+    LabelsGet,
+    LabelsCreate,
+    LabelsDelete,
+}
+
+impl GmailMethod {
+    pub const ALL: [GmailMethod; 4] = [
+        GmailMethod::LabelsList,
+        GmailMethod::LabelsGet,
+        GmailMethod::LabelsCreate,
+        GmailMethod::LabelsDelete,
+    ];
+}
+
+impl Display for GmailMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::LabelsList => write!(f, "labels.list"),
+            Self::LabelsGet => write!(f, "labels.get"),
+            Self::LabelsCreate => write!(f, "labels.create"),
+            Self::LabelsDelete => write!(f, "labels.delete"),
+        }
+    }
+}
+
+impl FromStr for GmailMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GmailMethod::ALL
+            .into_iter()
+            .find(|method| method.to_string() == s)
+            .ok_or_else(|| format!("Unknown Gmail method: {}", s))
+    }
+}
+
+/// Unit cost per method. The defaults follow Google's published table, but
+/// they change from time to time, so any of them can be overridden.
+#[derive(Debug, Clone)]
+pub struct QuotaCosts(HashMap<GmailMethod, u32>);
+
+impl Default for QuotaCosts {
+    fn default() -> Self {
+        Self(HashMap::from([
+            (GmailMethod::LabelsList, 1),
+            (GmailMethod::LabelsGet, 1),
+            (GmailMethod::LabelsCreate, 5),
+            (GmailMethod::LabelsDelete, 5),
+        ]))
+    }
+}
+
+impl QuotaCosts {
+    pub fn cost(&self, method: GmailMethod) -> u32 {
+        self.0.get(&method).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, method: GmailMethod, units: u32) {
+        self.0.insert(method, units);
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    used: HashMap<GmailMethod, u64>,
+}
+
+/// A plain token bucket. Each call asks for its method's cost in units and
+/// sleeps until the bucket has refilled enough to pay for it. The lock is
+/// never held across the sleep, so concurrent callers queue up fairly well
+/// without needing an async mutex.
+#[derive(Debug)]
+pub struct QuotaLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    costs: QuotaCosts,
+    bucket: Mutex<Bucket>,
+}
+
+impl Default for QuotaLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_REFILL_PER_SECOND, QuotaCosts::default())
+    }
+}
+
+impl QuotaLimiter {
+    pub fn new(capacity: u32, refill_per_second: u32, costs: QuotaCosts) -> Self {
+        Self {
+            capacity: capacity as f64,
+            refill_per_second: refill_per_second.max(1) as f64,
+            costs,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity as f64,
+                last_refill: Instant::now(),
+                used: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn with_costs(costs: QuotaCosts) -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_REFILL_PER_SECOND, costs)
+    }
+
+    /// Wait until `method` can be afforded, then spend its units.
+    pub async fn acquire(&self, method: GmailMethod) -> Result<(), Error> {
+        let cost = self.costs.cost(method) as f64;
+        if cost > self.capacity {
+            return Err(Error::QuotaCostExceedsCapacity);
+        }
+
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().map_err(|_| Error::QuotaLimiterPoisoned)?;
+                self.refill(&mut bucket);
+
+                if bucket.tokens >= cost {
+                    bucket.tokens -= cost;
+                    *bucket.used.entry(method).or_default() += cost as u64;
+                    return Ok(());
+                }
+
+                (cost - bucket.tokens) / self.refill_per_second
+            };
+
+            sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        bucket.last_refill = now;
+    }
+
+    /// Total units spent through this limiter so far.
+    pub fn units_used(&self) -> u64 {
+        self.usage().values().sum()
+    }
+
+    /// Units spent so far, broken down by method.
+    pub fn usage(&self) -> HashMap<GmailMethod, u64> {
+        self.bucket
+            .lock()
+            .map(|bucket| bucket.used.clone())
+            .unwrap_or_default()
+    }
+}
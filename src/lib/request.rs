@@ -1,167 +1,614 @@
-use std::{sync::{Arc, Mutex}};
-use tokio::{sync::mpsc::{self, Sender}, task::{JoinHandle, spawn}};
+use std::future::Future;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 
+use jsonwebtoken::jwk::JwkSet;
 use oauth2::{
-    basic::BasicClient, reqwest::async_http_client, AccessToken, AuthUrl, AuthorizationCode,
-    ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    basic::{
+        BasicErrorResponse, BasicErrorResponseType, BasicRevocationErrorResponse,
+        BasicTokenIntrospectionResponse, BasicTokenType,
+    },
+    revocation::RevocationErrorResponseType,
+    AuthType, AuthUrl, AuthorizationCode, Client, ClientId,
+    ClientSecret, CsrfToken, DeviceAuthorizationUrl, PkceCodeChallenge, PkceCodeVerifier,
+    devicecode::StandardDeviceAuthorizationResponse, RedirectUrl, RequestTokenError,
+    RevocationUrl, Scope, StandardRevocableToken, TokenUrl,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Method, Url,
 };
-use reqwest::Url;
 
 use crate::{
-    configuration::Configuration,
+    callback,
+    configuration::{
+        client_auth::{ClientType, TokenAuthMethod},
+        port::Port,
+        Configuration,
+    },
     error::Error,
+    event::{Event, Events, Observer},
+    id_token::{self, Expected},
     localhost_oauth_server::{self},
+    provider::{self, Provider},
+    process,
+    quota::{GmailMethod, QuotaLimiter},
+    token::{OidcTokenResponse, Token},
+    transport::{self, BoxFuture, HttpRequest, HttpResponse, Transport},
 };
 
-/// The requirements of Iced mean that there are a lot of very specific and
-/// sometimes unpalatable decisions I had to make while designing this lib.
-/// I'm quite pleased that I was able to successfully play hot potato with the
-/// terribly finicky PkceCodeVerifier and the kill signal for the TCPListener
-/// in localhost_oauth_server.
-/// 
-/// The interface for GmailLabelRequest is less clean than I'd like, but I have
-/// to put this project down at some point, it is only a portfolio piece after
-/// all.
+/// What the device flow hands back before polling starts: the code for the
+/// user to type in and the URL to type it in at.
+pub type DeviceAuthorization = StandardDeviceAuthorizationResponse;
+
+/// `BasicClient`, but keeping the ID token from the token response.
+type OAuthClient = Client<
+    BasicErrorResponse,
+    OidcTokenResponse,
+    BasicTokenType,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
+/// The requirements of Iced meant playing hot potato with the terribly
+/// finicky PkceCodeVerifier for a long time, behind an `Arc<Mutex<Option>>`
+/// that every clone shared. Now the request carries its place in the flow in
+/// its type instead, and the verifier moves from state to state by value:
+///
+/// `Unauthorized` → `AwaitingCallback` (or `AwaitingDevice`) → `Authorized`
+///
+/// Only an `Authorized` request can call the API, and a code can only be
+/// exchanged by the request that sent the user off to get it, so the old
+/// runtime failures for a missing verifier or token can't happen. A bare
+/// `GmailLabelRequest` is an authorized one.
+///
+/// Despite the name, nothing here is Gmail specific any more, the endpoints
+/// and scopes all come from the `Provider`.
 #[derive(Debug, Clone)]
-pub struct GmailLabelRequest {
-    oauth_client: oauth2::basic::BasicClient,
-    pub oauth_auth_url: Url,
-    pub pkce_verifier: Arc<Mutex<Option<PkceCodeVerifier>>>,
-    pub token: Option<AccessToken>,
-    kill_signal: Option<Sender<()>>,
+pub struct GmailLabelRequest<S = Authorized> {
+    oauth_client: OAuthClient,
+    local_port: Port,
+    pub quota: Arc<QuotaLimiter>,
+    provider: Arc<dyn Provider>,
+    // What an ID token has to match, and where to find the keys it's signed
+    // with. None if the provider can't give us ID tokens we could check.
+    identity: Option<(Expected, String)>,
+    events: Events,
+    cancel: CancellationToken,
+    transport: Arc<dyn Transport>,
+    state: S,
 }
 
-impl GmailLabelRequest {
-    pub async fn new(
-        config: &Configuration,
-    ) -> Result<GmailLabelRequest, Box<dyn std::error::Error>> {
-        let (auth_url, oauth_client, pkce_verifier) = Self::oauth2_initiation(config).await?;
+/// Configured, but with nothing to show for it yet.
+#[derive(Debug, Clone)]
+pub struct Unauthorized;
+
+/// The user has been sent to the authorization URL. This is the one state
+/// that can't be cloned, since there's only one verifier to go with the code.
+#[derive(Debug)]
+pub struct AwaitingCallback {
+    auth_url: Url,
+    pkce_verifier: PkceCodeVerifier,
+    csrf_token: CsrfToken,
+    nonce: Option<String>,
+}
+
+/// The device flow has started, and the user has a code to type in.
+#[derive(Debug, Clone)]
+pub struct AwaitingDevice {
+    details: DeviceAuthorization,
+}
+
+/// Holding a token, so ready for the API.
+#[derive(Debug, Clone)]
+pub struct Authorized {
+    token: Token,
+    // Only the code flow's ID token can echo the nonce, and only the first.
+    nonce: Option<String>,
+}
+
+impl<S> GmailLabelRequest<S> {
+    /// Swap in a limiter with different limits or unit costs. The limiter is
+    /// shared between clones, so every clone of a request spends from the
+    /// same bucket.
+    pub fn with_quota(mut self, quota: Arc<QuotaLimiter>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Report progress to `observer` from here on, including from clones.
+    pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.events = Events::new(observer);
+        self
+    }
+
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Share a cancellation token with whatever else it should stop, e.g.
+    /// everything a CLI run does.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Cancelling this stops the listener, token requests and API calls of
+    /// this request and all its clones, with `Error::Cancelled`. Cancelling
+    /// is an ordinary, non-blocking call, so async and sync code alike can
+    /// do it. A cancelled request stays cancelled; start a new one.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Send everything, token requests and API calls both, through
+    /// `transport` instead of the one the request was made with.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// For the requests that happen around this one, like fetching the
+    /// signing keys, so they go out the same way.
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        self.transport.clone()
+    }
+
+    /// The transport in the shape oauth2 wants it.
+    fn http<'a>(
+        &'a self,
+    ) -> impl Fn(HttpRequest) -> BoxFuture<'a, Result<HttpResponse, Error>> + 'a {
+        move |request| self.transport.send(request)
+    }
+
+    async fn until_cancelled<T>(
+        &self,
+        future: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        tokio::select! {
+            _ = self.cancel.cancelled() => Err(Error::Cancelled),
+            result = future => result,
+        }
+    }
+
+    pub fn provider(&self) -> &dyn Provider {
+        self.provider.as_ref()
+    }
+
+    /// Where the keys for checking this request's ID tokens are, if it will
+    /// get any worth checking.
+    pub fn jwks_url(&self) -> Option<&str> {
+        self.identity.as_ref().map(|(_, url)| url.as_str())
+    }
+
+    /// Whether the provider has a revocation endpoint for `revoke` to call.
+    pub fn can_revoke(&self) -> bool {
+        self.oauth_client.revocation_url().is_some()
+    }
+
+    fn into_state<T>(self, state: T) -> GmailLabelRequest<T> {
+        self.replace_state(state).0
+    }
+
+    fn replace_state<T>(self, state: T) -> (GmailLabelRequest<T>, S) {
+        let request = GmailLabelRequest {
+            oauth_client: self.oauth_client,
+            local_port: self.local_port,
+            quota: self.quota,
+            provider: self.provider,
+            identity: self.identity,
+            events: self.events,
+            cancel: self.cancel,
+            transport: self.transport,
+            state,
+        };
+        (request, self.state)
+    }
+
+    fn authorized(self, response: &OidcTokenResponse, nonce: Option<String>) -> GmailLabelRequest {
+        let token = Token::from_response(response, None);
+        self.into_state(Authorized { token, nonce })
+    }
+}
+
+impl GmailLabelRequest<Unauthorized> {
+    /// All of the request's HTTP traffic goes through `transport`, which is
+    /// usually `ReqwestTransport::from_configuration(&config.http)`. It's
+    /// passed in rather than built here so that one client can be shared
+    /// with discovery and every other request, and so that a canned one
+    /// never has a real client built alongside it.
+    pub fn new(config: &Configuration, transport: Arc<dyn Transport>) -> Result<Self, Error> {
+        let provider = provider::from_configuration(config);
+        let identity = Self::identity_check(config, provider.as_ref());
 
         Ok(Self {
-            oauth_client,
-            oauth_auth_url: auth_url,
-            pkce_verifier: Arc::new(Mutex::new(Some(pkce_verifier))),
-            token: None,
-            kill_signal: None,
+            oauth_client: Self::oauth2_initiation(config, provider.as_ref())?,
+            local_port: config.local_port.unwrap_or_default(),
+            quota: Arc::new(QuotaLimiter::default()),
+            provider,
+            identity,
+            events: Events::default(),
+            cancel: CancellationToken::new(),
+            transport,
+            state: Unauthorized,
         })
     }
 
-    pub fn kill(&mut self) -> Result<(), Error>{
-        match self.kill_signal.as_ref() {
-            Some(signal) => {
-                signal.blocking_send(()).map_err(Box::from)?;
-            },
-            None => {}
+    /// Resume with a token from a previous run instead of authorizing again.
+    pub fn with_token(self, token: Token) -> GmailLabelRequest {
+        self.into_state(Authorized { token, nonce: None })
+    }
+
+    /// Only worth asking for an ID token when there's an issuer and a key
+    /// set to check it against.
+    fn identity_check(
+        config: &Configuration,
+        provider: &dyn Provider,
+    ) -> Option<(Expected, String)> {
+        if !provider.scopes().iter().any(|scope| scope == "openid") {
+            return None;
+        }
+        let issuer = config.api.issuer_url.clone().or_else(|| provider.issuer())?;
+        let jwks_url = config.api.jwks_url.clone().or_else(|| provider.jwks_url())?;
+        let client_id = config.api.id.clone()?;
+
+        let bare_issuer = issuer.trim_start_matches("https://").to_string();
+        let expected = Expected {
+            issuers: vec![issuer, bare_issuer],
+            client_id,
+            nonce: None,
         };
-        Ok(())
+
+        Some((expected, jwks_url))
     }
 
-    async fn oauth2_initiation(
+    fn oauth2_initiation(
         config: &Configuration,
-    ) -> Result<(Url, BasicClient, PkceCodeVerifier), Box<dyn std::error::Error>> {
-        let client = BasicClient::new(
+        provider: &dyn Provider,
+    ) -> Result<OAuthClient, Error> {
+        let client = OAuthClient::new(
             config
                 .api
                 .id
                 .clone()
                 .map(ClientId::new)
-                .ok_or("Missing client id")?,
-            config.api.secret.clone().map(ClientSecret::new),
+                .ok_or_else(|| Error::Configuration("Missing client id".to_string()))?,
+            config
+                .api
+                .secret
+                .clone()
+                .filter(|_| config.api.client_type == ClientType::Confidential)
+                .map(ClientSecret::new),
             config
                 .api
                 .auth_url
                 .clone()
+                .or_else(|| provider.auth_url())
                 .map(AuthUrl::new)
-                .ok_or("Missing auth url")??,
+                .ok_or_else(|| Error::Configuration("Missing auth url".to_string()))??,
             config
                 .api
                 .token_url
                 .clone()
+                .or_else(|| provider.token_url())
                 .map(TokenUrl::new)
                 .transpose()?,
         )
+        .set_auth_type(match config.api.token_auth_method {
+            TokenAuthMethod::ClientSecretBasic => AuthType::BasicAuth,
+            TokenAuthMethod::ClientSecretPost => AuthType::RequestBody,
+        })
         .set_redirect_uri(RedirectUrl::new(
             "http://localhost:".to_string() + &config.local_port.unwrap_or_default().to_string(),
         )?);
 
+        let device_auth_url = config.api.device_auth_url.clone().or_else(|| provider.device_auth_url());
+        let client = match device_auth_url {
+            Some(url) => client.set_device_authorization_url(DeviceAuthorizationUrl::new(url)?),
+            None => client,
+        };
+
+        let revocation_url = config.api.revocation_url.clone().or_else(|| provider.revocation_url());
+        let client = match revocation_url {
+            Some(url) => client.set_revocation_uri(RevocationUrl::new(url)?),
+            None => client,
+        };
+
+        Ok(client)
+    }
+
+    /// Build the authorization URL to send the user to, along with the PKCE
+    /// verifier, state and nonce that its answer will have to match.
+    pub fn authorize(self) -> GmailLabelRequest<AwaitingCallback> {
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let nonce = self
+            .identity
+            .as_ref()
+            .map(|_| CsrfToken::new_random().secret().clone());
 
-        let (auth_url, _csrf_token) = client
+        let authorize = self
+            .oauth_client
             .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new(
-                "https://www.googleapis.com/auth/gmail.labels".to_string(),
-            ))
-            .set_pkce_challenge(pkce_challenge)
+            .add_scopes(self.provider.scopes().into_iter().map(Scope::new))
+            .set_pkce_challenge(pkce_challenge);
+
+        let authorize = match nonce.clone() {
+            Some(nonce) => authorize.add_extra_param("nonce", nonce),
+            None => authorize,
+        };
+
+        let (auth_url, csrf_token) = self
+            .provider
+            .extra_auth_params()
+            .into_iter()
+            .fold(authorize, |authorize, (name, value)| authorize.add_extra_param(name, value))
             .url();
 
-        Ok((auth_url, client, pkce_verifier))
+        self.events.emit(Event::UrlReady { url: auth_url.to_string() });
+
+        self.into_state(AwaitingCallback {
+            auth_url,
+            pkce_verifier,
+            csrf_token,
+            nonce,
+        })
     }
 
-    pub fn oauth2_token_receiver(
-        mut self,
-        config: Configuration,
-    ) -> (GmailLabelRequest, JoinHandle<Result<std::string::String, Error>>) {
-        let (kill_sender, kill_receiver) = mpsc::channel(1);
+    /// Start the device flow (RFC 8628), for machines with no browser at all.
+    /// Show the user the code and verification URL from `details`, then call
+    /// `oauth2_device_token` to wait for them.
+    pub async fn oauth2_device_authorization(
+        self,
+    ) -> Result<GmailLabelRequest<AwaitingDevice>, Error> {
+        let device_code = self
+            .oauth_client
+            .exchange_device_code()?
+            .add_scopes(self.provider.scopes().into_iter().map(Scope::new));
+        let details = self
+            .until_cancelled(async { Ok(device_code.request_async(self.http()).await?) })
+            .await?;
 
-        self.kill_signal = Some(kill_sender.clone());
+        Ok(self.into_state(AwaitingDevice { details }))
+    }
+}
 
-        let join_handle = spawn(localhost_oauth_server::raw_tcp_listener(
-            config.local_port.unwrap_or_default(),
-            kill_receiver,
-        ));
+impl GmailLabelRequest<AwaitingCallback> {
+    pub fn auth_url(&self) -> &Url {
+        &self.state.auth_url
+    }
 
-        (self, join_handle)
-    
+    /// Wait on the loopback listener for the browser to come back with a
+    /// code, then trade it for a token.
+    pub async fn receive_callback(self) -> Result<GmailLabelRequest, Error> {
+        let code = localhost_oauth_server::raw_tcp_listener(
+            self.local_port,
+            self.state.csrf_token.secret().clone(),
+            &self.cancel,
+            &self.events,
+        )
+        .await?;
+        self.events.emit(Event::CodeReceived);
+
+        self.oauth2_token_verification(code).await
     }
 
-    pub async fn oauth2_token_verification(
-        mut self,
-        code: String,
-    ) -> Result<GmailLabelRequest, Error> {
-        // This is always a bit of an intricate dance, but a Mutex Result is
-        // particularly temporary, and needs immediate handling.
-        let pkce_verifier = {
-            self.pkce_verifier
-                .try_lock()
-                .map_err(|err| {
-                    eprintln!("{:?}", err);
-                    Error::PkceCodeVerifierLocked
-                })?
-                .take()
-        }
-        .ok_or(Error::PkceCodeVerifierMissing)?;
+    /// For when the loopback listener can't be reached, e.g. over SSH. The
+    /// user pastes back the redirect URL from their browser, or just the
+    /// code, and a URL gets the same checks the listener would apply.
+    pub fn parse_authorization_response(&self, input: &str) -> Result<String, Error> {
+        let code = callback::parse_authorization_response(input, self.state.csrf_token.secret())?;
+        self.events.emit(Event::CodeReceived);
+        Ok(code)
+    }
+
+    pub async fn oauth2_token_verification(self, code: String) -> Result<GmailLabelRequest, Error> {
+        let (request, awaiting) = self.replace_state(Unauthorized);
 
-        let token_result: AccessToken = self
+        let exchange = request
             .oauth_client
             .exchange_code(AuthorizationCode::new(code))
-            .set_pkce_verifier(pkce_verifier)
-            .request_async(async_http_client)
-            .await
-            .map_err(Box::from)?
-            .access_token()
-            .clone();
+            .set_pkce_verifier(awaiting.pkce_verifier);
+        let token_response = request
+            .until_cancelled(async { Ok(exchange.request_async(request.http()).await?) })
+            .await?;
 
-        self.token = Some(token_result);
+        request.events.emit(Event::TokenExchanged);
+        Ok(request.authorized(&token_response, awaiting.nonce))
+    }
+}
+
+impl GmailLabelRequest<AwaitingDevice> {
+    /// What to show the user: the code, and where to enter it.
+    pub fn details(&self) -> &DeviceAuthorization {
+        &self.state.details
+    }
+
+    /// Poll the token endpoint until the user approves or the device code
+    /// expires. oauth2 takes care of `authorization_pending`, and of backing
+    /// off by five seconds on every `slow_down`.
+    pub async fn oauth2_device_token(self) -> Result<GmailLabelRequest, Error> {
+        let poll = self
+            .oauth_client
+            .exchange_device_access_token(&self.state.details)
+            .request_async(self.http(), tokio::time::sleep, None);
+        let token_response = self.until_cancelled(async { Ok(poll.await?) }).await?;
+
+        self.events.emit(Event::TokenExchanged);
+        Ok(self.authorized(&token_response, None))
+    }
+}
+
+impl GmailLabelRequest<Authorized> {
+    pub fn token(&self) -> &Token {
+        &self.state.token
+    }
+
+    /// Check the ID token that came with the last token response and record
+    /// whose it is. A response without one leaves the token as it was.
+    pub fn verify_identity(mut self, keys: &JwkSet) -> Result<GmailLabelRequest, Error> {
+        let (expected, _) = match &self.identity {
+            Some(identity) => identity,
+            None => return Ok(self),
+        };
+        let raw = match self.state.token.id_token.take() {
+            Some(raw) => raw,
+            None => return Ok(self),
+        };
+
+        let expected = Expected {
+            nonce: self.state.nonce.take(),
+            ..expected.clone()
+        };
+        self.state.token.email = id_token::validate(&raw, keys, &expected)?.email;
 
         Ok(self)
     }
 
+    /// Trade the refresh token for a new access token. The caller is
+    /// responsible for saving the result, since it may or may not want it
+    /// persisted.
+    pub async fn oauth2_token_refresh(mut self) -> Result<GmailLabelRequest, Error> {
+        let token = &self.state.token;
+        let refresh_token = token.refresh_token.as_ref().ok_or(Error::MissingToken)?;
+
+        let refresh = self.oauth_client.exchange_refresh_token(refresh_token);
+        let token_response = self
+            .until_cancelled(async { Ok(refresh.request_async(self.http()).await?) })
+            .await?;
+
+        self.state = Authorized {
+            token: Token::from_response(&token_response, Some(token)),
+            nonce: None,
+        };
+        self.events.emit(Event::RefreshPerformed);
+
+        Ok(self)
+    }
+
+    /// Revoke the tokens at the provider (RFC 7009), refresh token first
+    /// since that ends the whole grant. The request is used up, as there's
+    /// nothing left it could do. Deleting the stored copy is up to the caller.
+    pub async fn revoke(self) -> Result<(), Error> {
+        let token = &self.state.token;
+
+        let revocable = token
+            .refresh_token
+            .clone()
+            .map(StandardRevocableToken::RefreshToken)
+            .into_iter()
+            .chain([StandardRevocableToken::AccessToken(token.access_token.clone())]);
+
+        self.until_cancelled(async {
+            for revocable in revocable {
+                let result = self
+                    .oauth_client
+                    .revoke_token(revocable)?
+                    .request_async(self.http())
+                    .await;
+
+                match result {
+                    Err(RequestTokenError::ServerResponse(response))
+                        if already_revoked(&response) => {}
+                    result => result?,
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
     pub async fn get_labels(self) -> Result<(String, GmailLabelRequest), Error> {
-        let client = reqwest::Client::new();
+        let res = self
+            .gmail_call(GmailMethod::LabelsList, Method::GET, self.resource_url()?, None)
+            .await?;
+        if let Ok(ids) = process::label_ids(&res) {
+            self.events.emit(Event::LabelsFetched { count: ids.len() });
+        }
+
+        Ok((res, self))
+    }
 
-        let token = self.token.as_ref().ok_or(Error::MissingToken)?;
+    /// labels.list only returns names and ids. Message and thread counts
+    /// need a labels.get per label, which is where the quota starts to bite.
+    pub async fn get_label(self, id: &str) -> Result<(String, GmailLabelRequest), Error> {
+        let url = format!("{}/{}", self.resource_url()?, id);
+        let res = self.gmail_call(GmailMethod::LabelsGet, Method::GET, url, None).await?;
+
+        Ok((res, self))
+    }
 
-        let res = client
-            .get("https://www.googleapis.com/gmail/v1/users/me/labels")
-            .bearer_auth(token.secret())
-            .send()
-            .await
-            .map_err(Box::from)?
-            .text()
-            .await
-            .map_err(Box::from)?;
+    /// Returns the new label as Gmail echoes it back.
+    pub async fn create_label(self, name: &str) -> Result<(String, GmailLabelRequest), Error> {
+        let body = serde_json::json!({ "name": name });
+        let res = self
+            .gmail_call(
+                GmailMethod::LabelsCreate,
+                Method::POST,
+                self.resource_url()?,
+                Some(body),
+            )
+            .await?;
 
         Ok((res, self))
     }
+
+    pub async fn delete_label(self, id: &str) -> Result<GmailLabelRequest, Error> {
+        let url = format!("{}/{}", self.resource_url()?, id);
+        self.gmail_call(GmailMethod::LabelsDelete, Method::DELETE, url, None).await?;
+
+        Ok(self)
+    }
+
+    fn resource_url(&self) -> Result<String, Error> {
+        self.provider
+            .resource_url()
+            .ok_or_else(|| Error::Configuration("Missing resource url".to_string()))
+    }
+
+    /// Every API call ends here, so this is where they're all made
+    /// cancellable, including any wait for quota.
+    async fn gmail_call(
+        &self,
+        method: GmailMethod,
+        http_method: Method,
+        url: String,
+        body: Option<serde_json::Value>,
+    ) -> Result<String, Error> {
+        let token = &self.state.token;
+
+        self.until_cancelled(async {
+            self.quota.acquire(method).await?;
+
+            let bearer = HeaderValue::from_str(&format!("Bearer {}", token.access_token.secret()))
+                .map_err(|_| Error::UnexpectedResponse("unusable access token".to_string()))?;
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, bearer);
+            if body.is_some() {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+
+            let request = HttpRequest {
+                url: Url::parse(&url)?,
+                method: http_method,
+                headers,
+                body: body.map(|body| body.to_string().into_bytes()).unwrap_or_default(),
+            };
+
+            transport::text(self.transport.send(request).await?)
+        })
+        .await
+    }
+}
+
+/// Google answers invalid_token for a token that's already gone, including
+/// an access token whose grant was just revoked along with its refresh
+/// token. Gone is the goal, so that counts as success.
+fn already_revoked(response: &BasicRevocationErrorResponse) -> bool {
+    matches!(
+        response.error(),
+        RevocationErrorResponseType::Basic(BasicErrorResponseType::Extension(error))
+            if error == "invalid_token"
+    )
 }
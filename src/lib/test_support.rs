@@ -0,0 +1,560 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::sync::{Arc, Mutex};
+
+use oauth2::url::form_urlencoded;
+use reqwest::{StatusCode, Url};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::configuration::{
+    client_auth::{ClientType, TokenAuthMethod},
+    ApiConfiguration, Configuration, HttpConfiguration,
+};
+use crate::error::Error;
+use crate::fake_gmail::{self, label};
+use crate::provider::ProviderKind;
+use crate::transport::{BoxFuture, HttpRequest, HttpResponse, ReqwestTransport, Transport};
+
+const LABELS_PATH: &str = "/gmail/v1/users/me/labels";
+const CLIENT_ID: &str = "mock-client";
+const SCOPE: &str = "https://www.googleapis.com/auth/gmail.labels";
+const DEVICE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// How many times a device code is polled before the user gets round to
+/// approving it.
+const PENDING_POLLS: usize = 2;
+
+/// Something for the mock to get wrong on purpose, to see how the caller
+/// copes. A failure sticks until it's replaced or cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The user turns the consent screen down, so the redirect carries
+    /// `error=access_denied` instead of a code. In the device flow, polling
+    /// ends in `access_denied` instead of a token.
+    AccessDenied,
+    /// The token endpoint refuses every code and refresh token.
+    InvalidGrant,
+    /// The labels API refuses every access token with a 401.
+    Unauthorized,
+    /// The labels API is out of quota, and answers 429.
+    RateLimited,
+    /// Every request is read, and then never answered.
+    Stalled,
+    /// The first poll for a device code is told to `slow_down`, and the
+    /// next one approved.
+    SlowDown,
+    /// Nobody approves the device code in time, so polling ends in
+    /// `expired_token`.
+    ExpiredToken,
+}
+
+/// How the last request reached the mock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seen {
+    pub user_agent: Option<String>,
+    /// It came through a proxy, i.e. it named the full URL rather than just
+    /// the path. The mock answers those too, so it can be its own proxy.
+    pub proxied: bool,
+}
+
+/// A stand-in for Google on a local port: an authorization endpoint that
+/// approves straight away, token, device and revocation endpoints, and the
+/// Gmail labels API with a few canned labels. Point a request at it with
+/// `configuration`, and play the browser with `browse`.
+///
+/// It speaks just enough HTTP/1.1 for reqwest, one request per connection,
+/// in the same spirit as the loopback listener. It stops when dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+struct State {
+    failure: Option<Failure>,
+    issued: usize,
+    codes: HashSet<String>,
+    access_tokens: HashSet<String>,
+    refresh_tokens: HashSet<String>,
+    /// Outstanding device codes, and how often each has been polled.
+    devices: HashMap<String, usize>,
+    /// The access token issued alongside each refresh token, which goes
+    /// with it when the grant is revoked.
+    grants: HashMap<String, String>,
+    /// Every token anyone asked to revoke, in order, good or not.
+    revoked: Vec<String>,
+    labels: fake_gmail::Labels,
+    last_seen: Option<Seen>,
+    received: usize,
+}
+
+struct Request {
+    method: String,
+    target: String,
+    authorization: Option<String>,
+    user_agent: Option<String>,
+    body: String,
+}
+
+struct Response {
+    status: StatusCode,
+    location: Option<String>,
+    body: Option<Value>,
+}
+
+impl MockServer {
+    /// Bind to a free port and start answering.
+    pub async fn start() -> Result<Self, Error> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::new()));
+
+        let task = tokio::spawn(serve(listener, state.clone()));
+
+        Ok(Self { address, state, task })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    /// A public client profile with every endpoint pointing here, and the
+    /// loopback listener on `local_port`.
+    pub fn configuration(&self, local_port: u16) -> Configuration {
+        Configuration {
+            profile: "mock".to_string(),
+            api: ApiConfiguration {
+                id: Some(CLIENT_ID.to_string()),
+                secret: None,
+                auth_url: Some(self.url("/auth")),
+                token_url: Some(self.url("/token")),
+                device_auth_url: Some(self.url("/device/code")),
+                provider: ProviderKind::Custom,
+                scopes: Some(SCOPE.to_string()),
+                resource_url: Some(self.url(LABELS_PATH)),
+                issuer_url: None,
+                revocation_url: Some(format!("https://{}/revoke", self.address)),
+                userinfo_url: None,
+                jwks_url: None,
+                client_type: ClientType::Public,
+                token_auth_method: TokenAuthMethod::default(),
+            },
+            local_port: Some(local_port.into()),
+            http: HttpConfiguration::default(),
+        }
+    }
+
+    /// Start failing with `failure`, or stop failing with None.
+    pub fn fail_with(&self, failure: Option<Failure>) {
+        self.state.lock().expect("mock state poisoned").failure = failure;
+    }
+
+    pub fn last_seen(&self) -> Option<Seen> {
+        self.state.lock().expect("mock state poisoned").last_seen.clone()
+    }
+
+    /// How many requests have reached the mock, answered or not.
+    pub fn received(&self) -> usize {
+        self.state.lock().expect("mock state poisoned").received
+    }
+
+    /// oauth2 won't revoke over anything but HTTPS, which the mock doesn't
+    /// speak. So the revocation URL in `configuration` says https, and a
+    /// request needs this transport, which takes it to the mock over plain
+    /// HTTP anyway, to revoke anything.
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        Arc::new(Downgrade { address: self.address, inner: ReqwestTransport::default() })
+    }
+
+    /// The tokens sent to the revocation endpoint so far, in the order they
+    /// arrived.
+    pub fn revoked(&self) -> Vec<String> {
+        self.state.lock().expect("mock state poisoned").revoked.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Debug)]
+struct Downgrade {
+    address: SocketAddr,
+    inner: ReqwestTransport,
+}
+
+impl Transport for Downgrade {
+    fn send(&self, mut request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, Error>> {
+        if request.url.port() == Some(self.address.port()) {
+            // Only fails for schemes that can't swap, and https can.
+            let _ = request.url.set_scheme("http");
+        }
+        self.inner.send(request)
+    }
+}
+
+/// Open `url` the way a browser would, following the authorization
+/// endpoint's redirect back to the loopback listener.
+pub async fn browse(url: &Url) -> Result<(), Error> {
+    reqwest::get(url.clone()).await?;
+    Ok(())
+}
+
+/// A port nothing is listening on, for the loopback listener. Each test
+/// wants its own so they can run in parallel.
+pub fn free_port() -> Result<u16, Error> {
+    Ok(StdTcpListener::bind(("127.0.0.1", 0))?.local_addr()?.port())
+}
+
+async fn serve(listener: TcpListener, state: Arc<Mutex<State>>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(answer(stream, state.clone()));
+    }
+}
+
+// A connection that goes wrong is the client's problem, so errors are
+// simply dropped along with the connection.
+async fn answer(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let request = match read_request(&mut stream).await {
+        Some(request) => request,
+        None => return,
+    };
+
+    let (response, failure) = {
+        let mut state = state.lock().expect("mock state poisoned");
+        (state.route(&request), state.failure)
+    };
+    if failure == Some(Failure::Stalled) {
+        return std::future::pending().await;
+    }
+    let _ = stream.write_all(&response.into_bytes()).await;
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut raw = Vec::new();
+    let mut buffer = [0; 4096];
+
+    let header_end = loop {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        raw.extend_from_slice(&buffer[..read]);
+        if let Some(end) = raw.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&raw[..header_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    let mut user_agent = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().ok()?,
+                "authorization" => authorization = Some(value.trim().to_string()),
+                "user-agent" => user_agent = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    while raw.len() < header_end + content_length {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        raw.extend_from_slice(&buffer[..read]);
+    }
+    let body = String::from_utf8_lossy(&raw[header_end..header_end + content_length]).into_owned();
+
+    Some(Request { method, target, authorization, user_agent, body })
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            failure: None,
+            issued: 0,
+            codes: HashSet::new(),
+            access_tokens: HashSet::new(),
+            refresh_tokens: HashSet::new(),
+            devices: HashMap::new(),
+            grants: HashMap::new(),
+            revoked: Vec::new(),
+            labels: fake_gmail::Labels::new(vec![
+                label("INBOX", "INBOX", "system", None, 12, 3),
+                label("Label_1", "Work", "user", None, 5, 1),
+                label("Label_2", "Work/Projects", "user", None, 2, 0),
+            ]),
+            last_seen: None,
+            received: 0,
+        }
+    }
+
+    fn route(&mut self, request: &Request) -> Response {
+        // A proxy is sent the whole URL. This is the only host there is, so
+        // it can just be dropped.
+        let absolute = request.target.strip_prefix("http://");
+        let target = match absolute.and_then(|rest| rest.find('/').map(|start| &rest[start..])) {
+            Some(path) => path,
+            None => request.target.as_str(),
+        };
+        self.received += 1;
+        self.last_seen = Some(Seen {
+            user_agent: request.user_agent.clone(),
+            proxied: absolute.is_some(),
+        });
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        match (request.method.as_str(), path) {
+            ("GET", "/auth") => self.authorize(query),
+            ("POST", "/token") => self.token(&request.body),
+            ("POST", "/device/code") => self.device_code(),
+            ("POST", "/revoke") => self.revoke(&request.body),
+            (_, path) if path.starts_with(LABELS_PATH) => {
+                let id = path[LABELS_PATH.len()..].trim_start_matches('/');
+                self.labels(request, id)
+            }
+            _ => Response::new(StatusCode::NOT_FOUND, None),
+        }
+    }
+
+    /// No consent screen, just straight back to the redirect URI.
+    fn authorize(&mut self, query: &str) -> Response {
+        let params = parse_form(query);
+        let redirect_uri = match params.iter().find(|(name, _)| name == "redirect_uri") {
+            Some((_, uri)) => uri.clone(),
+            None => return Response::new(StatusCode::BAD_REQUEST, None),
+        };
+        let state = params
+            .iter()
+            .find(|(name, _)| name == "state")
+            .map(|(_, state)| state.clone())
+            .unwrap_or_default();
+
+        let answer = match self.failure {
+            Some(Failure::AccessDenied) => ("error", "access_denied".to_string()),
+            _ => {
+                let code = self.issue("code");
+                self.codes.insert(code.clone());
+                ("code", code)
+            }
+        };
+
+        let location: String = form_urlencoded::Serializer::new(format!("{}?", redirect_uri))
+            .append_pair(answer.0, &answer.1)
+            .append_pair("state", &state)
+            .finish();
+
+        Response {
+            status: StatusCode::FOUND,
+            location: Some(location),
+            body: None,
+        }
+    }
+
+    fn token(&mut self, body: &str) -> Response {
+        let params = parse_form(body);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        if param("grant_type") == Some(DEVICE_GRANT) {
+            return self.device_token(param("device_code").unwrap_or_default());
+        }
+
+        let accepted = self.failure != Some(Failure::InvalidGrant)
+            && match param("grant_type") {
+                // One use per code, and PKCE or no deal.
+                Some("authorization_code") => {
+                    param("code_verifier").is_some()
+                        && param("code").is_some_and(|code| self.codes.remove(code))
+                }
+                Some("refresh_token") => param("refresh_token")
+                    .is_some_and(|token| self.refresh_tokens.contains(token)),
+                _ => false,
+            };
+
+        if !accepted {
+            let error = json!({
+                "error": "invalid_grant",
+                "error_description": "Bad Request",
+            });
+            return Response::new(StatusCode::BAD_REQUEST, Some(error));
+        }
+
+        self.grant()
+    }
+
+    /// The device flow's first step. The interval is zero so the tests
+    /// don't sit through real polling delays.
+    fn device_code(&mut self) -> Response {
+        let device_code = self.issue("device");
+        self.devices.insert(device_code.clone(), 0);
+        let user_code = format!("MOCK-{}", self.issued);
+
+        let verification_uri = "https://www.google.com/device";
+        let body = json!({
+            "device_code": device_code,
+            "user_code": user_code,
+            "verification_uri": verification_uri,
+            "verification_uri_complete": format!("{}?user_code={}", verification_uri, user_code),
+            "expires_in": 300,
+            "interval": 0,
+        });
+        Response::new(StatusCode::OK, Some(body))
+    }
+
+    /// Each device code is pending for `PENDING_POLLS` polls, then approved,
+    /// unless a failure says otherwise. Once it's answered for good, it's
+    /// gone.
+    fn device_token(&mut self, device_code: &str) -> Response {
+        let polls = match self.devices.get_mut(device_code) {
+            Some(polls) => {
+                *polls += 1;
+                *polls
+            }
+            None => return oauth_error("invalid_grant"),
+        };
+
+        let answer = match self.failure {
+            // Straight to approval after, since every poll from here on
+            // waits an extra five seconds.
+            Some(Failure::SlowDown) if polls == 1 => Some("slow_down"),
+            Some(Failure::SlowDown) => None,
+            _ if polls <= PENDING_POLLS => Some("authorization_pending"),
+            Some(Failure::AccessDenied) => Some("access_denied"),
+            Some(Failure::ExpiredToken) => Some("expired_token"),
+            Some(Failure::InvalidGrant) => Some("invalid_grant"),
+            _ => None,
+        };
+
+        match answer {
+            Some(error @ ("slow_down" | "authorization_pending")) => oauth_error(error),
+            Some(error) => {
+                self.devices.remove(device_code);
+                oauth_error(error)
+            }
+            None => {
+                self.devices.remove(device_code);
+                self.grant()
+            }
+        }
+    }
+
+    /// A fresh access and refresh token pair.
+    fn grant(&mut self) -> Response {
+        let access_token = self.issue("access");
+        self.access_tokens.insert(access_token.clone());
+        let refresh_token = self.issue("refresh");
+        self.refresh_tokens.insert(refresh_token.clone());
+        self.grants.insert(refresh_token.clone(), access_token.clone());
+
+        let token = json!({
+            "access_token": access_token,
+            "refresh_token": refresh_token,
+            "token_type": "Bearer",
+            "expires_in": 3599,
+            "scope": SCOPE,
+        });
+        Response::new(StatusCode::OK, Some(token))
+    }
+
+    /// Like Google: a refresh token takes its access token down with it,
+    /// and a token that's unknown or already gone is `invalid_token`.
+    fn revoke(&mut self, body: &str) -> Response {
+        let params = parse_form(body);
+        let token = match params.iter().find(|(name, _)| name == "token") {
+            Some((_, token)) => token.clone(),
+            None => return oauth_error("invalid_request"),
+        };
+        self.revoked.push(token.clone());
+
+        if self.refresh_tokens.remove(&token) {
+            if let Some(access_token) = self.grants.remove(&token) {
+                self.access_tokens.remove(&access_token);
+            }
+        } else if !self.access_tokens.remove(&token) {
+            return oauth_error("invalid_token");
+        }
+
+        Response::new(StatusCode::OK, None)
+    }
+
+    fn labels(&mut self, request: &Request, id: &str) -> Response {
+        let token = request
+            .authorization
+            .as_deref()
+            .and_then(|header| header.strip_prefix("Bearer "));
+        let authorized = token.is_some_and(|token| self.access_tokens.contains(token));
+
+        match self.failure {
+            Some(Failure::Unauthorized) => return api_error(StatusCode::UNAUTHORIZED),
+            _ if !authorized => return api_error(StatusCode::UNAUTHORIZED),
+            Some(Failure::RateLimited) => return api_error(StatusCode::TOO_MANY_REQUESTS),
+            _ => {}
+        }
+
+        let (status, body) = self.labels.answer(&request.method, id, request.body.as_bytes());
+        Response::new(status, body)
+    }
+
+    fn issue(&mut self, kind: &str) -> String {
+        self.issued += 1;
+        format!("mock-{}-{}", kind, self.issued)
+    }
+}
+
+impl Response {
+    fn new(status: StatusCode, body: Option<Value>) -> Self {
+        Self { status, location: None, body }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let body = self.body.map(|body| body.to_string()).unwrap_or_default();
+        let location = self
+            .location
+            .map(|location| format!("Location: {}\r\n", location))
+            .unwrap_or_default();
+
+        format!(
+            "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            location,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+/// Errors the way the token endpoint writes them.
+fn oauth_error(error: &str) -> Response {
+    Response::new(StatusCode::BAD_REQUEST, Some(json!({ "error": error })))
+}
+
+/// Errors the way Google's APIs write them.
+fn api_error(status: StatusCode) -> Response {
+    Response::new(status, Some(fake_gmail::error(status)))
+}
+
+fn parse_form(input: &str) -> Vec<(String, String)> {
+    form_urlencoded::parse(input.as_bytes()).into_owned().collect()
+}
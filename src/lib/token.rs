@@ -0,0 +1,196 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use oauth2::{
+    basic::BasicTokenType, AccessToken, ExtraTokenFields, RefreshToken, StandardTokenResponse,
+    TokenResponse,
+};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::db;
+use crate::error::Error;
+use crate::provider::Provider;
+
+/// Refresh a little before the server's deadline so a token doesn't expire
+/// between the check and the request that uses it.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// The one thing OpenID Connect adds to a token response.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IdTokenFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+/// oauth2's `BasicTokenResponse` throws the ID token away, this keeps it.
+pub type OidcTokenResponse = StandardTokenResponse<IdTokenFields, BasicTokenType>;
+
+/// An access token plus everything we need to keep using it across runs.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub access_token: AccessToken,
+    pub refresh_token: Option<RefreshToken>,
+    /// Seconds since the unix epoch.
+    pub expires_at: Option<u64>,
+    pub scopes: Vec<String>,
+    /// The account's address, once an ID token has been validated.
+    pub email: Option<String>,
+    /// When the access token was issued, by a login or a refresh. Tokens
+    /// saved by older versions don't know.
+    pub refreshed_at: Option<u64>,
+    /// The unvalidated ID token from the last response. Only kept in memory,
+    /// its claims are what's worth saving.
+    pub id_token: Option<String>,
+}
+
+impl Token {
+    /// The refresh grant is allowed to omit the refresh token, in which case
+    /// the old one is still valid, so it's carried over from `previous`. So
+    /// are the scopes, and the account until a new ID token has been checked.
+    pub fn from_response(response: &OidcTokenResponse, previous: Option<&Token>) -> Self {
+        Self {
+            access_token: response.access_token().clone(),
+            refresh_token: response
+                .refresh_token()
+                .cloned()
+                .or_else(|| previous.and_then(|token| token.refresh_token.clone())),
+            expires_at: response.expires_in().map(|expires_in| now() + expires_in.as_secs()),
+            scopes: response
+                .scopes()
+                .map(|scopes| scopes.iter().map(|scope| scope.to_string()).collect())
+                .or_else(|| previous.map(|token| token.scopes.clone()))
+                .unwrap_or_default(),
+            email: previous.and_then(|token| token.email.clone()),
+            refreshed_at: Some(now()),
+            id_token: response.extra_fields().id_token.clone(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| now() + EXPIRY_MARGIN.as_secs() >= expires_at)
+            .unwrap_or(false)
+    }
+
+    /// Seconds until the access token expires, zero once it has.
+    pub fn seconds_remaining(&self) -> Option<u64> {
+        self.expires_at.map(|expires_at| expires_at.saturating_sub(now()))
+    }
+
+    pub fn load(db: &Connection, profile: &str) -> Result<Option<Self>, Error> {
+        let mut stmt = db.prepare(db::SELECT_TOKEN)?;
+        let token = stmt
+            .query_map(params![profile], |row| {
+                Ok(Token {
+                    access_token: AccessToken::new(row.get(0)?),
+                    refresh_token: row.get::<_, Option<String>>(1)?.map(RefreshToken::new),
+                    expires_at: row.get(2)?,
+                    scopes: row
+                        .get::<_, Option<String>>(3)?
+                        .map(|scopes| scopes.split_whitespace().map(str::to_owned).collect())
+                        .unwrap_or_default(),
+                    email: row.get(4)?,
+                    refreshed_at: row.get(5)?,
+                    id_token: None,
+                })
+            })?
+            .next()
+            .transpose()?;
+
+        Ok(token)
+    }
+
+    pub fn save(&self, db: &Connection, profile: &str) -> Result<(), Error> {
+        db::save_token(
+            self.access_token.secret(),
+            self.refresh_token.as_ref().map(|token| token.secret().as_str()),
+            self.expires_at,
+            &self.scopes.join(" "),
+            self.email.as_deref(),
+            self.refreshed_at,
+            profile,
+            db,
+        )
+    }
+
+    pub fn delete(db: &Connection, profile: &str) -> Result<(), Error> {
+        db::delete_token(profile, db)
+    }
+}
+
+/// Everything worth knowing about a profile's token without asking the
+/// provider, mostly whether the user will have to go through consent again.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Status {
+    pub authorized: bool,
+    pub account: Option<String>,
+    pub expired: bool,
+    pub expires_in_seconds: Option<u64>,
+    pub has_refresh_token: bool,
+    /// Seconds since the unix epoch.
+    pub refreshed_at: Option<u64>,
+    pub requested_scopes: Vec<String>,
+    /// Empty when the server didn't list them, which per RFC 6749 means it
+    /// granted exactly what was requested.
+    pub granted_scopes: Vec<String>,
+    /// Requested but not granted, either refused at consent or added to the
+    /// configuration since.
+    pub missing_scopes: Vec<String>,
+    /// No token, one that can't be refreshed, or one that doesn't cover
+    /// what is asked for now. Any of them mean a trip through the browser.
+    pub needs_consent: bool,
+}
+
+impl Status {
+    pub fn new(token: Option<&Token>, provider: &dyn Provider) -> Self {
+        let requested_scopes = provider.scopes();
+        let token = match token {
+            Some(token) => token,
+            None => {
+                return Self {
+                    requested_scopes,
+                    needs_consent: true,
+                    ..Self::default()
+                }
+            }
+        };
+
+        let missing_scopes = if token.scopes.is_empty() {
+            Vec::new()
+        } else {
+            requested_scopes
+                .iter()
+                .filter(|scope| !provider.grants(scope, &token.scopes))
+                .cloned()
+                .collect()
+        };
+
+        let expired = token.is_expired();
+        Self {
+            authorized: true,
+            account: token.email.clone(),
+            expired,
+            expires_in_seconds: token.seconds_remaining(),
+            has_refresh_token: token.refresh_token.is_some(),
+            refreshed_at: token.refreshed_at,
+            requested_scopes,
+            granted_scopes: token.scopes.clone(),
+            needs_consent: (expired && token.refresh_token.is_none()) || !missing_scopes.is_empty(),
+            missing_scopes,
+        }
+    }
+
+    /// How long ago the token was issued or last refreshed.
+    pub fn seconds_since_refresh(&self) -> Option<u64> {
+        self.refreshed_at.map(|at| now().saturating_sub(at))
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
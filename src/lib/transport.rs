@@ -0,0 +1,169 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
+
+pub use oauth2::{HttpRequest, HttpResponse};
+use reqwest::{header::HeaderMap, Certificate, Method, StatusCode, Url};
+
+use crate::configuration::HttpConfiguration;
+use crate::error::Error;
+
+/// What the client uses for anything `HttpConfiguration` leaves unset.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_USER_AGENT: &str = concat!("rust_requester/", env!("CARGO_PKG_VERSION"));
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Whatever actually puts requests on the wire, for the token endpoint and
+/// the API alike. The default is reqwest, but anything that can answer an
+/// `HttpRequest` will do, which is how the requests get tested without
+/// talking to Google.
+///
+/// A response that arrived is `Ok`, whatever its status. `Err` is for not
+/// getting one at all.
+pub trait Transport: Debug + Send + Sync {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, Error>>;
+}
+
+/// The real thing. Redirects are never followed, same as oauth2's own
+/// client, so a token endpoint can't bounce the code off somewhere else.
+///
+/// It's cheap to clone, and clones share their connections, so one of these
+/// can carry a whole session.
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+    /// reqwest only has a timeout for the whole request, which a slow but
+    /// steady download could hit, so this one is ours.
+    read_timeout: Option<Duration>,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            read_timeout: None,
+        }
+    }
+
+    /// A client with the configuration's timeouts, user agent, proxy and
+    /// extra CAs. Without a proxy set, reqwest still picks one up from
+    /// `HTTPS_PROXY` and friends.
+    pub fn from_configuration(config: &HttpConfiguration) -> Result<Self, Error> {
+        let seconds = |value: Option<u64>, default| value.map_or(default, Duration::from_secs);
+        let mut builder = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .connect_timeout(seconds(config.connect_timeout, DEFAULT_CONNECT_TIMEOUT))
+            .user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT));
+
+        if let Some(proxy_url) = &config.proxy_url {
+            let proxy = reqwest::Proxy::all(proxy_url)
+                .map_err(|err| Error::Configuration(format!("Invalid proxy url: {}", err)))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = &config.ca_bundle_path {
+            for certificate in ca_bundle(Path::new(path))? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            read_timeout: Some(seconds(config.read_timeout, DEFAULT_READ_TIMEOUT)),
+        })
+    }
+
+    async fn within<T>(
+        &self,
+        future: impl Future<Output = reqwest::Result<T>>,
+    ) -> Result<T, Error> {
+        match self.read_timeout {
+            Some(limit) => tokio::time::timeout(limit, future).await.map_err(|_| Error::TimedOut)?,
+            None => future.await,
+        }
+        .map_err(Error::from)
+    }
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        Self::from_configuration(&HttpConfiguration::default())
+            .unwrap_or_else(|_| Self::new(reqwest::Client::default()))
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, Error>> {
+        Box::pin(async move {
+            let send = self
+                .client
+                .request(request.method, request.url)
+                .headers(request.headers)
+                .body(request.body)
+                .send();
+            let mut response = self.within(send).await?;
+
+            // The read timeout is per chunk, not for the whole body.
+            let mut body = Vec::new();
+            while let Some(chunk) = self.within(response.chunk()).await? {
+                body.extend_from_slice(&chunk);
+            }
+
+            Ok(HttpResponse {
+                status_code: response.status(),
+                headers: response.headers().clone(),
+                body,
+            })
+        })
+    }
+}
+
+/// A plain GET, for the documents around the OAuth flow: discovery and the
+/// signing keys. Going through the transport means they get the same
+/// proxy and CAs as everything else.
+pub async fn get(transport: &dyn Transport, url: &str) -> Result<String, Error> {
+    let request = HttpRequest {
+        url: Url::parse(url)?,
+        method: Method::GET,
+        headers: HeaderMap::new(),
+        body: Vec::new(),
+    };
+    text(transport.send(request).await?)
+}
+
+/// The body of a successful response. 401 gets its own error, since it
+/// means authorizing again.
+pub fn text(response: HttpResponse) -> Result<String, Error> {
+    match response.status_code {
+        status if status.is_success() => Ok(String::from_utf8_lossy(&response.body).into_owned()),
+        StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
+        status => Err(Error::HttpStatus(status.as_u16())),
+    }
+}
+
+/// Every certificate in a PEM bundle. reqwest only takes them one at a
+/// time, and corporate bundles usually hold a whole chain.
+fn ca_bundle(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let bad_bundle = |reason: String| {
+        Error::Configuration(format!("Unusable CA bundle {}: {}", path.display(), reason))
+    };
+
+    let pem = std::fs::read_to_string(path).map_err(|err| bad_bundle(err.to_string()))?;
+    let certificates = pem
+        .split_inclusive(PEM_END)
+        .filter_map(|block| block.find(PEM_BEGIN).map(|start| &block[start..]))
+        .map(|block| Certificate::from_pem(block.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| bad_bundle(err.to_string()))?;
+
+    if certificates.is_empty() {
+        return Err(bad_bundle("no certificates in it".to_string()));
+    }
+    Ok(certificates)
+}
@@ -0,0 +1,64 @@
+use rust_requester::callback::{
+    is_bare_code, parse_authorization_response, parse_redirect, request_target,
+};
+use rust_requester::error::Error;
+
+const STATE: &str = "expected-state";
+
+#[test]
+fn pasted_redirect_url_gives_its_code() {
+    let pasted = "http://localhost:8080/?state=expected-state&code=4/abc&scope=x";
+    assert_eq!(parse_authorization_response(pasted, STATE).unwrap(), "4/abc");
+}
+
+#[test]
+fn pasted_query_string_gives_its_code() {
+    let pasted = "  code=4/abc&state=expected-state\n";
+    assert_eq!(parse_authorization_response(pasted, STATE).unwrap(), "4/abc");
+}
+
+#[test]
+fn bare_code_is_taken_unchecked() {
+    assert_eq!(parse_authorization_response(" 4/abc\n", STATE).unwrap(), "4/abc");
+    assert!(is_bare_code(" 4/abc\n"));
+    assert!(!is_bare_code("code=4/abc&state=expected-state"));
+}
+
+#[test]
+fn pasted_redirect_with_the_wrong_state_is_refused() {
+    let pasted = "http://localhost:8080/?code=4/abc&state=someone-elses";
+    let error = parse_authorization_response(pasted, STATE).unwrap_err();
+    assert!(matches!(error, Error::StateMismatch), "got {:?}", error);
+}
+
+#[test]
+fn pasted_redirect_without_a_state_is_refused() {
+    let error = parse_authorization_response("http://localhost/?code=4/abc", STATE).unwrap_err();
+    assert!(matches!(error, Error::StateMismatch), "got {:?}", error);
+}
+
+#[test]
+fn denial_is_reported_as_such() {
+    let pasted = "http://localhost/?error=access_denied&state=expected-state";
+    match parse_authorization_response(pasted, STATE) {
+        Err(Error::AuthorizationDenied(reason)) => assert_eq!(reason, "access_denied"),
+        other => panic!("expected access_denied, got {:?}", other),
+    }
+}
+
+#[test]
+fn listener_request_goes_through_the_same_checks() {
+    let raw = "GET /?code=4/abc&state=expected-state HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let target = request_target(raw).unwrap();
+    assert_eq!(parse_redirect(target, STATE).unwrap(), "4/abc");
+
+    let forged = "GET /?code=4/abc&state=forged HTTP/1.1\r\n\r\n";
+    let error = parse_redirect(request_target(forged).unwrap(), STATE).unwrap_err();
+    assert!(matches!(error, Error::StateMismatch), "got {:?}", error);
+}
+
+#[test]
+fn listener_ignores_requests_without_a_code() {
+    let error = parse_redirect("/favicon.ico", STATE).unwrap_err();
+    assert!(matches!(error, Error::StateMismatch), "got {:?}", error);
+}
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use rust_requester::cassette::{Interaction, Recorder, Replayer};
+use rust_requester::configuration::client_auth::{ClientType, TokenAuthMethod};
+use rust_requester::error::Error;
+use rust_requester::event::Event;
+use rust_requester::process;
+use rust_requester::request::GmailLabelRequest;
+use rust_requester::test_support::{self, MockServer};
+use rust_requester::transport::ReqwestTransport;
+use tokio::sync::mpsc::unbounded_channel;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn record_then_replay_without_the_server() {
+    let path = std::env::temp_dir().join(format!("cassette-{}.json", std::process::id()));
+    let server = MockServer::start().await.unwrap();
+    let config = server.configuration(test_support::free_port().unwrap());
+
+    // Record a login, a refresh and a labels.list against the mock.
+    let recorder = Arc::new(Recorder::new(Arc::new(ReqwestTransport::default()), &path));
+    let (sender, mut events) = unbounded_channel();
+    let request = GmailLabelRequest::new(&config, recorder)
+        .unwrap()
+        .with_observer(Arc::new(sender))
+        .authorize();
+    let url = request.auth_url().clone();
+    let browser = tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let Event::ListenerBound { .. } = event {
+                return test_support::browse(&url).await;
+            }
+        }
+        Ok(())
+    });
+    let request = request.receive_callback().await.unwrap();
+    browser.await.unwrap().unwrap();
+    let request = request.oauth2_token_refresh().await.unwrap();
+    let (recorded, _) = request.get_labels().await.unwrap();
+
+    let cassette = std::fs::read_to_string(&path).unwrap();
+    assert!(!cassette.contains("mock-access-"), "{}", cassette);
+    assert!(!cassette.contains("mock-refresh-"), "{}", cassette);
+    assert!(!cassette.contains("mock-code-"), "{}", cassette);
+    drop(server);
+
+    // The same flow again, with nothing listening and nobody at a browser.
+    let replayer = Arc::new(Replayer::load(&path).unwrap());
+    let request = GmailLabelRequest::new(&config, replayer)
+        .unwrap()
+        .authorize()
+        .oauth2_token_verification("anything".to_string())
+        .await
+        .unwrap();
+    let request = request.oauth2_token_refresh().await.unwrap();
+    let (replayed, request) = request.get_labels().await.unwrap();
+
+    assert_eq!(process::label_ids(&replayed).unwrap(), process::label_ids(&recorded).unwrap());
+    assert!(request.get_labels().await.is_err(), "only one labels.list was recorded");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn confidential_device_flow_is_scrubbed() {
+    let path = std::env::temp_dir().join(format!("cassette-device-{}.json", std::process::id()));
+    let server = MockServer::start().await.unwrap();
+    let mut config = server.configuration(test_support::free_port().unwrap());
+    config.api.client_type = ClientType::Confidential;
+    config.api.token_auth_method = TokenAuthMethod::ClientSecretPost;
+    config.api.secret = Some("mock-secret".to_string());
+
+    let recorder = Arc::new(Recorder::new(Arc::new(ReqwestTransport::default()), &path));
+    let request = GmailLabelRequest::new(&config, recorder)
+        .unwrap()
+        .oauth2_device_authorization()
+        .await
+        .unwrap();
+    let user_code = request.details().user_code().secret().clone();
+    let request = request.oauth2_device_token().await.unwrap();
+    request.oauth2_token_refresh().await.unwrap();
+
+    let cassette = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    for secret in ["mock-secret", user_code.as_str(), "mock-device-", "mock-access-", "mock-refresh-"] {
+        assert!(!cassette.contains(secret), "{} in {}", secret, cassette);
+    }
+
+    let interactions: Vec<Interaction> = serde_json::from_str(&cassette).unwrap();
+    let forms: Vec<_> = interactions
+        .iter()
+        .filter(|interaction| interaction.url.ends_with("/token"))
+        .map(|interaction| interaction.request_body.as_str())
+        .collect();
+    assert!(!forms.is_empty());
+    for form in forms {
+        assert!(form.contains("client_secret=REDACTED"), "{}", form);
+    }
+
+    // The verification URL is kept, only the code in it goes.
+    let device = &interactions[0].response_body;
+    assert!(device.contains("https://www.google.com/device?user_code=REDACTED"), "{}", device);
+}
+
+#[test]
+fn missing_cassette_is_a_configuration_error() {
+    let error = Replayer::load("/nonexistent/cassette.json").unwrap_err();
+    assert!(matches!(error, Error::Configuration(_)), "got {:?}", error);
+    assert!(error.to_string().contains("/nonexistent/cassette.json"), "{}", error);
+}
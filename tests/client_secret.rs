@@ -0,0 +1,82 @@
+use rust_requester::configuration::{client_secret::ClientSecret, port::Port, Configuration};
+use rust_requester::db;
+use rust_requester::error::Error;
+
+const INSTALLED: &str = r#"{
+    "installed": {
+        "client_id": "123.apps.googleusercontent.com",
+        "project_id": "requester",
+        "auth_uri": "https://accounts.google.com/o/oauth2/auth",
+        "token_uri": "https://oauth2.googleapis.com/token",
+        "auth_provider_x509_cert_url": "https://www.googleapis.com/oauth2/v1/certs",
+        "client_secret": "installed-secret",
+        "redirect_uris": ["http://localhost"]
+    }
+}"#;
+
+const WEB: &str = r#"{
+    "web": {
+        "client_id": "456.apps.googleusercontent.com",
+        "auth_uri": "https://accounts.google.com/o/oauth2/auth",
+        "token_uri": "https://oauth2.googleapis.com/token",
+        "client_secret": "web-secret",
+        "redirect_uris": [
+            "https://example.com/callback",
+            "http://localhost:8080/elsewhere",
+            "http://127.0.0.1:9000"
+        ]
+    }
+}"#;
+
+fn blank_configuration() -> Configuration {
+    Configuration::init(&db::init_in_memory().unwrap(), "secret").unwrap()
+}
+
+#[test]
+fn installed_client() {
+    let secret = ClientSecret::from_json_str(INSTALLED).unwrap();
+    assert_eq!(secret.client_id, "123.apps.googleusercontent.com");
+    assert_eq!(secret.client_secret.as_deref(), Some("installed-secret"));
+    assert_eq!(secret.token_uri, "https://oauth2.googleapis.com/token");
+
+    // A bare localhost means any port, so there's none to take.
+    assert!(secret.redirect_port().is_none());
+}
+
+#[test]
+fn web_client() {
+    let secret = ClientSecret::from_json_str(WEB).unwrap();
+    assert_eq!(secret.client_id, "456.apps.googleusercontent.com");
+    assert_eq!(secret.client_secret.as_deref(), Some("web-secret"));
+}
+
+#[test]
+fn first_loopback_redirect_with_a_port_wins() {
+    let secret = ClientSecret::from_json_str(WEB).unwrap();
+    assert_eq!(secret.redirect_port().map(Port::as_u16), Some(8080));
+}
+
+#[test]
+fn missing_client_id_is_rejected() {
+    let raw = r#"{ "installed": { "auth_uri": "https://a", "token_uri": "https://t" } }"#;
+
+    let error = ClientSecret::from_json_str(raw).unwrap_err();
+    assert!(matches!(error, Error::Json(_)), "got {:?}", error);
+}
+
+#[test]
+fn neither_installed_nor_web_is_rejected() {
+    let error = ClientSecret::from_json_str(r#"{ "other": {} }"#).unwrap_err();
+    assert!(matches!(error, Error::Configuration(_)), "got {:?}", error);
+}
+
+#[test]
+fn apply_keeps_what_the_file_leaves_out() {
+    let mut config = blank_configuration();
+    config.local_port = Some(Port::from(7000));
+
+    let config = ClientSecret::from_json_str(INSTALLED).unwrap().apply(config);
+    assert_eq!(config.api.id.as_deref(), Some("123.apps.googleusercontent.com"));
+    assert_eq!(config.api.auth_url.as_deref(), Some("https://accounts.google.com/o/oauth2/auth"));
+    assert_eq!(config.local_port.map(Port::as_u16), Some(7000));
+}
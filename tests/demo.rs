@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use rust_requester::db;
+use rust_requester::demo::{self, Demo};
+use rust_requester::fetch;
+use rust_requester::label::Label;
+use rust_requester::process;
+use rust_requester::request::GmailLabelRequest;
+
+#[tokio::test]
+async fn demo_labels_go_through_the_database() {
+    let db = db::init_in_memory().unwrap();
+    let config = demo::configuration();
+
+    let request = GmailLabelRequest::new(&config, Arc::new(Demo::default()))
+        .unwrap()
+        .authorize()
+        .oauth2_token_verification("demo".to_string())
+        .await
+        .unwrap();
+    request.token().save(&db, &config.profile).unwrap();
+
+    let labels = fetch::store_labels(request, true, &config.profile, &db).await.unwrap();
+    let saved = db::get_full_labels(&config.profile, &db).unwrap();
+    assert_eq!(labels.len(), saved.len());
+
+    let inbox = saved.iter().find(|label| label.id == "INBOX").unwrap();
+    assert_eq!(inbox.label_type, "system");
+    assert!(inbox.messages_unread.is_some());
+
+    let nested = saved.iter().find(|label| label.name == "Work/Projects/Apollo").unwrap();
+    assert_eq!(nested.label_type, "user");
+    assert!(nested.color.is_some());
+}
+
+#[tokio::test]
+async fn demo_device_login_then_sign_out() {
+    let demo = Arc::new(Demo::default());
+    let request = GmailLabelRequest::new(&demo::configuration(), demo)
+        .unwrap()
+        .oauth2_device_authorization()
+        .await
+        .unwrap();
+    assert_eq!(request.details().user_code().secret(), "DEMO-CODE");
+
+    let request = request.oauth2_device_token().await.unwrap();
+    assert!(request.can_revoke());
+    request.revoke().await.unwrap();
+}
+
+#[tokio::test]
+async fn deleted_label_ids_are_not_reused() {
+    let request = GmailLabelRequest::new(&demo::configuration(), Arc::new(Demo::default()))
+        .unwrap()
+        .authorize()
+        .oauth2_token_verification("demo".to_string())
+        .await
+        .unwrap();
+
+    let (first, request) = request.create_label("A").await.unwrap();
+    let (second, request) = request.create_label("B").await.unwrap();
+    let first: Label = serde_json::from_str(&first).unwrap();
+    let request = request.delete_label(&first.id).await.unwrap();
+    let (third, request) = request.create_label("C").await.unwrap();
+
+    let second: Label = serde_json::from_str(&second).unwrap();
+    let third: Label = serde_json::from_str(&third).unwrap();
+    assert_ne!(second.id, third.id);
+    let (raw_list, _) = request.get_labels().await.unwrap();
+    assert_eq!(process::find_label_id(&raw_list, "C").unwrap(), Some(third.id));
+}
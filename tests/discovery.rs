@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::{header::HeaderMap, StatusCode};
+use rust_requester::configuration::discovery::{self, ProviderMetadata, MAX_AGE};
+use rust_requester::db;
+use rust_requester::error::Error;
+use rust_requester::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+
+const ISSUER: &str = "https://issuer.example.com";
+
+// Fetched just over a day ago.
+fn stale() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    now - MAX_AGE.as_secs() - 1
+}
+
+fn document(issuer: &str, token_endpoint: &str) -> String {
+    serde_json::json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": token_endpoint,
+        "jwks_uri": format!("{}/keys", issuer),
+    })
+    .to_string()
+}
+
+/// Answers every request with the same document, or a 503 without one,
+/// and counts how often it was asked.
+#[derive(Debug)]
+struct Issuer {
+    document: Option<String>,
+    calls: AtomicUsize,
+}
+
+impl Issuer {
+    fn new(document: Option<String>) -> Arc<Self> {
+        Arc::new(Self { document, calls: AtomicUsize::new(0) })
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl Transport for Issuer {
+    fn send(&self, _request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, Error>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let response = match &self.document {
+            Some(document) => HttpResponse {
+                status_code: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: document.clone().into_bytes(),
+            },
+            None => HttpResponse {
+                status_code: StatusCode::SERVICE_UNAVAILABLE,
+                headers: HeaderMap::new(),
+                body: Vec::new(),
+            },
+        };
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+#[test]
+fn document_for_another_issuer_is_rejected() {
+    let raw = document("https://elsewhere.example.com", "https://elsewhere.example.com/token");
+
+    let error = ProviderMetadata::from_json_str(&raw, ISSUER).unwrap_err();
+    assert!(matches!(error, Error::UnexpectedResponse(_)), "got {:?}", error);
+}
+
+#[test]
+fn trailing_slash_still_matches() {
+    let raw = document(&format!("{}/", ISSUER), "https://issuer.example.com/token");
+    assert!(ProviderMetadata::from_json_str(&raw, ISSUER).is_ok());
+}
+
+#[tokio::test]
+async fn fetch_rejects_a_mismatched_issuer() {
+    let issuer = Issuer::new(Some(document("https://elsewhere.example.com", "https://t")));
+
+    let error = discovery::fetch(ISSUER.to_string(), issuer).await.unwrap_err();
+    assert!(matches!(error, Error::UnexpectedResponse(_)), "got {:?}", error);
+}
+
+#[test]
+fn cache_goes_stale_after_max_age() {
+    let db = db::init_in_memory().unwrap();
+    assert!(discovery::cached(ISSUER, &db).unwrap().is_none());
+
+    discovery::store(ISSUER, &document(ISSUER, "https://t"), &db).unwrap();
+    let (_, fresh) = discovery::cached(ISSUER, &db).unwrap().unwrap();
+    assert!(fresh);
+
+    db::save_discovery(ISSUER, &document(ISSUER, "https://t"), stale(), &db).unwrap();
+    let (_, fresh) = discovery::cached(ISSUER, &db).unwrap().unwrap();
+    assert!(!fresh);
+}
+
+#[tokio::test]
+async fn fresh_cache_is_used_without_asking() {
+    let db = db::init_in_memory().unwrap();
+    discovery::store(ISSUER, &document(ISSUER, "https://cached/token"), &db).unwrap();
+    let issuer = Issuer::new(Some(document(ISSUER, "https://fetched/token")));
+
+    let discovered = discovery::discover(ISSUER, &db, false, issuer.clone()).await.unwrap();
+    assert_eq!(discovered.metadata.token_endpoint, "https://cached/token");
+    assert_eq!(issuer.calls(), 0);
+
+    // Unless a refresh is asked for, which replaces the cached copy too.
+    let discovered = discovery::discover(ISSUER, &db, true, issuer.clone()).await.unwrap();
+    assert_eq!(discovered.metadata.token_endpoint, "https://fetched/token");
+    assert_eq!(issuer.calls(), 1);
+    let (cached, _) = discovery::cached(ISSUER, &db).unwrap().unwrap();
+    assert_eq!(cached.token_endpoint, "https://fetched/token");
+}
+
+#[tokio::test]
+async fn stale_cache_is_refetched() {
+    let db = db::init_in_memory().unwrap();
+    db::save_discovery(ISSUER, &document(ISSUER, "https://cached/token"), stale(), &db).unwrap();
+    let issuer = Issuer::new(Some(document(ISSUER, "https://fetched/token")));
+
+    let discovered = discovery::discover(ISSUER, &db, false, issuer.clone()).await.unwrap();
+    assert_eq!(discovered.metadata.token_endpoint, "https://fetched/token");
+    assert!(discovered.stale.is_none());
+    assert_eq!(issuer.calls(), 1);
+}
+
+#[tokio::test]
+async fn unreachable_issuer_falls_back_to_the_stale_copy() {
+    let db = db::init_in_memory().unwrap();
+    db::save_discovery(ISSUER, &document(ISSUER, "https://cached/token"), stale(), &db).unwrap();
+
+    let discovered = discovery::discover(ISSUER, &db, false, Issuer::new(None)).await.unwrap();
+    assert_eq!(discovered.metadata.token_endpoint, "https://cached/token");
+    assert!(matches!(discovered.stale, Some(Error::HttpStatus(503))), "{:?}", discovered.stale);
+
+    // With nothing cached there's nothing to fall back on.
+    let db = db::init_in_memory().unwrap();
+    let error = discovery::discover(ISSUER, &db, false, Issuer::new(None)).await.unwrap_err();
+    assert!(matches!(error, Error::HttpStatus(503)), "got {:?}", error);
+}
@@ -0,0 +1,138 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{encode, jwk::JwkSet, Algorithm, EncodingKey, Header};
+use rust_requester::error::Error;
+use rust_requester::id_token::{self, Expected, Identity};
+use serde_json::{json, Value};
+
+// Two throwaway RSA keys. The published set has both: "first" says it's
+// for RS256, "second" leaves its alg out.
+const FIRST: &[u8] = include_bytes!("fixtures/first.pem");
+const SECOND: &[u8] = include_bytes!("fixtures/second.pem");
+const JWKS: &str = include_str!("fixtures/jwks.json");
+
+const ISSUER: &str = "https://accounts.example.com";
+const CLIENT_ID: &str = "client-id";
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn keys() -> JwkSet {
+    serde_json::from_str(JWKS).unwrap()
+}
+
+fn expected() -> Expected {
+    Expected {
+        issuers: vec![ISSUER.to_string()],
+        client_id: CLIENT_ID.to_string(),
+        nonce: Some("nonce".to_string()),
+    }
+}
+
+fn claims() -> Value {
+    json!({
+        "iss": ISSUER,
+        "aud": CLIENT_ID,
+        "sub": "1234",
+        "email": "someone@example.com",
+        "nonce": "nonce",
+        "iat": now(),
+        "exp": now() + 3600,
+    })
+}
+
+fn sign(claims: &Value, kid: Option<&str>, key: &[u8]) -> String {
+    sign_as(Algorithm::RS256, claims, kid, key)
+}
+
+fn sign_as(algorithm: Algorithm, claims: &Value, kid: Option<&str>, key: &[u8]) -> String {
+    let mut header = Header::new(algorithm);
+    header.kid = kid.map(str::to_owned);
+    encode(&header, claims, &EncodingKey::from_rsa_pem(key).unwrap()).unwrap()
+}
+
+fn rejected(token: &str) -> String {
+    match id_token::validate(token, &keys(), &expected()).unwrap_err() {
+        Error::InvalidIdToken(reason) => reason,
+        error => panic!("got {:?}", error),
+    }
+}
+
+#[test]
+fn valid_token_passes() {
+    let token = sign(&claims(), Some("first"), FIRST);
+
+    let identity = id_token::validate(&token, &keys(), &expected()).unwrap();
+    assert_eq!(
+        identity,
+        Identity { subject: "1234".to_string(), email: Some("someone@example.com".to_string()) }
+    );
+}
+
+#[test]
+fn key_without_an_alg_goes_by_its_type() {
+    let token = sign(&claims(), Some("second"), SECOND);
+    assert!(id_token::validate(&token, &keys(), &expected()).is_ok());
+}
+
+#[test]
+fn wrong_audience_fails() {
+    let mut claims = claims();
+    claims["aud"] = json!("someone-else");
+    assert_eq!(rejected(&sign(&claims, Some("first"), FIRST)), "InvalidAudience");
+}
+
+#[test]
+fn wrong_issuer_fails() {
+    let mut claims = claims();
+    claims["iss"] = json!("https://evil.example.com");
+    assert_eq!(rejected(&sign(&claims, Some("first"), FIRST)), "InvalidIssuer");
+}
+
+#[test]
+fn expired_token_fails() {
+    // Well past the minute of leeway.
+    let mut claims = claims();
+    claims["exp"] = json!(now() - 3600);
+    assert_eq!(rejected(&sign(&claims, Some("first"), FIRST)), "ExpiredSignature");
+}
+
+#[test]
+fn nonce_mismatch_fails() {
+    let mut claims = claims();
+    claims["nonce"] = json!("another");
+    assert_eq!(rejected(&sign(&claims, Some("first"), FIRST)), "nonce mismatch");
+}
+
+#[test]
+fn unknown_kid_fails() {
+    assert_eq!(rejected(&sign(&claims(), Some("third"), FIRST)), "signed with an unknown key");
+}
+
+#[test]
+fn signed_by_the_other_key_fails() {
+    assert_eq!(rejected(&sign(&claims(), Some("first"), SECOND)), "InvalidSignature");
+}
+
+#[test]
+fn missing_kid_needs_a_single_key() {
+    let token = sign(&claims(), None, FIRST);
+    assert_eq!(rejected(&token), "signed with an unknown key");
+
+    let mut keys = keys();
+    keys.keys.truncate(1);
+    assert!(id_token::validate(&token, &keys, &expected()).is_ok());
+}
+
+#[test]
+fn header_alg_must_match_the_key() {
+    let reason = rejected(&sign_as(Algorithm::RS384, &claims(), Some("first"), FIRST));
+    assert_eq!(reason, "signed with RS384, but the key is for RS256");
+
+    // The classic: an HMAC token keyed with something public.
+    let mut header = Header::new(Algorithm::HS256);
+    header.kid = Some("first".to_string());
+    let token = encode(&header, &claims(), &EncodingKey::from_secret(JWKS.as_bytes())).unwrap();
+    assert_eq!(rejected(&token), "signed with HS256, but the key is for RS256");
+}
@@ -0,0 +1,465 @@
+use std::sync::Arc;
+
+use rust_requester::configuration::{Configuration, HttpConfiguration};
+use rust_requester::db;
+use rust_requester::error::Error;
+use rust_requester::event::Event;
+use rust_requester::fetch;
+use rust_requester::label::Label;
+use rust_requester::process;
+use rust_requester::request::GmailLabelRequest;
+use rust_requester::token::Token;
+use rust_requester::test_support::{self, Failure, MockServer, Seen};
+use rust_requester::transport::ReqwestTransport;
+use serde_json::Value;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_util::sync::CancellationToken;
+
+/// The whole loopback flow against the mock: authorize, wait for the
+/// listener, play the browser, and trade the code for a token.
+///
+/// The listener reads its connection with a blocking read, so the browser
+/// has to be off on another thread, like a real one would be. Hence the
+/// multi-threaded runtime on every test that logs in.
+async fn login(server: &MockServer) -> Result<GmailLabelRequest, Error> {
+    login_with(&server.configuration(test_support::free_port()?)).await
+}
+
+async fn login_with(config: &Configuration) -> Result<GmailLabelRequest, Error> {
+    let (sender, mut events) = unbounded_channel();
+    let transport = Arc::new(ReqwestTransport::from_configuration(&config.http)?);
+    let request = GmailLabelRequest::new(config, transport)?
+        .with_observer(Arc::new(sender))
+        .authorize();
+    let url = request.auth_url().clone();
+
+    let browser = tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let Event::ListenerBound { .. } = event {
+                return test_support::browse(&url).await;
+            }
+        }
+        Ok(())
+    });
+
+    let request = request.receive_callback().await;
+    browser.await.expect("browser panicked")?;
+    request
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn login_and_list_labels() {
+    let server = MockServer::start().await.unwrap();
+
+    let request = login(&server).await.unwrap();
+    assert!(request.token().refresh_token.is_some());
+
+    let (raw_list, _) = request.get_labels().await.unwrap();
+    let ids = process::label_ids(&raw_list).unwrap();
+    assert_eq!(ids, ["INBOX", "Label_1", "Label_2"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_create_and_delete_a_label() {
+    let server = MockServer::start().await.unwrap();
+    let request = login(&server).await.unwrap();
+
+    let (raw_label, request) = request.get_label("INBOX").await.unwrap();
+    let label: Label = serde_json::from_str(&raw_label).unwrap();
+    assert_eq!(label.messages_unread, Some(3));
+
+    let (raw_label, request) = request.create_label("Receipts").await.unwrap();
+    let label: Label = serde_json::from_str(&raw_label).unwrap();
+    assert_eq!(label.name, "Receipts");
+
+    let request = request.delete_label(&label.id).await.unwrap();
+    let (raw_list, _) = request.get_labels().await.unwrap();
+    assert_eq!(process::find_label_id(&raw_list, "Receipts").unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refresh_issues_a_working_token() {
+    let server = MockServer::start().await.unwrap();
+    let request = login(&server).await.unwrap();
+    let old_token = request.token().access_token.secret().clone();
+
+    let request = request.oauth2_token_refresh().await.unwrap();
+    assert_ne!(request.token().access_token.secret(), &old_token);
+    assert!(request.get_labels().await.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn access_denied_ends_the_wait() {
+    let server = MockServer::start().await.unwrap();
+    server.fail_with(Some(Failure::AccessDenied));
+
+    match login(&server).await {
+        Err(Error::AuthorizationDenied(reason)) => assert_eq!(reason, "access_denied"),
+        other => panic!("expected access_denied, got {:?}", other),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_grant_needs_reauthorization() {
+    let server = MockServer::start().await.unwrap();
+    server.fail_with(Some(Failure::InvalidGrant));
+
+    let error = login(&server).await.unwrap_err();
+    assert!(matches!(error, Error::OAuth(_)), "got {:?}", error);
+    assert_eq!(error.kind(), "needs_reauth");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_grant_on_refresh() {
+    let server = MockServer::start().await.unwrap();
+    let request = login(&server).await.unwrap();
+    server.fail_with(Some(Failure::InvalidGrant));
+
+    let error = request.oauth2_token_refresh().await.unwrap_err();
+    assert_eq!(error.kind(), "needs_reauth");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_access_token_is_unauthorized() {
+    let server = MockServer::start().await.unwrap();
+    let request = login(&server).await.unwrap();
+    server.fail_with(Some(Failure::Unauthorized));
+
+    let error = request.get_labels().await.unwrap_err();
+    assert!(matches!(error, Error::Unauthorized), "got {:?}", error);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rate_limit_keeps_its_status() {
+    let server = MockServer::start().await.unwrap();
+    let request = login(&server).await.unwrap();
+    server.fail_with(Some(Failure::RateLimited));
+
+    let error = request.get_labels().await.unwrap_err();
+    assert!(matches!(error, Error::HttpStatus(429)), "got {:?}", error);
+    assert_eq!(error.kind(), "network");
+}
+
+#[tokio::test]
+async fn cancelling_stops_the_listener() {
+    let server = MockServer::start().await.unwrap();
+    let config = server.configuration(test_support::free_port().unwrap());
+    let request = GmailLabelRequest::new(&config, server.transport()).unwrap().authorize();
+    request.cancellation_token().cancel();
+
+    let error = request.receive_callback().await.unwrap_err();
+    assert!(matches!(error, Error::Cancelled), "got {:?}", error);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn labels_list_is_gmail_shaped() {
+    let server = MockServer::start().await.unwrap();
+    let request = login(&server).await.unwrap();
+
+    let (raw_list, _) = request.get_labels().await.unwrap();
+    let list: Value = serde_json::from_str(&raw_list).unwrap();
+    let labels: Vec<Label> = serde_json::from_value(list["labels"].clone()).unwrap();
+    assert_eq!(labels[0].label_type, "system");
+    assert_eq!(labels[2].name, "Work/Projects");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn configured_client_is_used_for_token_and_api() {
+    let server = MockServer::start().await.unwrap();
+    let mut config = server.configuration(test_support::free_port().unwrap());
+    config.http.user_agent = Some("corp-tool/1.0".to_string());
+    config.http.proxy_url = Some(server.url(""));
+
+    let request = login_with(&config).await.unwrap();
+    let seen = Seen {
+        user_agent: Some("corp-tool/1.0".to_string()),
+        proxied: true,
+    };
+    assert_eq!(server.last_seen(), Some(seen.clone()));
+
+    request.get_labels().await.unwrap();
+    assert_eq!(server.last_seen(), Some(seen));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_server_hits_the_read_timeout() {
+    let server = MockServer::start().await.unwrap();
+    let mut config = server.configuration(test_support::free_port().unwrap());
+    config.http.read_timeout = Some(1);
+    let request = login_with(&config).await.unwrap();
+    server.fail_with(Some(Failure::Stalled));
+
+    let error = request.get_labels().await.unwrap_err();
+    assert!(matches!(error, Error::TimedOut), "got {:?}", error);
+    assert_eq!(error.kind(), "network");
+}
+
+#[test]
+fn missing_ca_bundle_is_a_configuration_error() {
+    let http = HttpConfiguration {
+        ca_bundle_path: Some("/nonexistent/ca.pem".to_string()),
+        ..HttpConfiguration::default()
+    };
+
+    let error = ReqwestTransport::from_configuration(&http).unwrap_err();
+    assert!(matches!(error, Error::Configuration(_)), "got {:?}", error);
+}
+
+/// Start the device flow against the mock and poll until it's answered for
+/// good. The mock's interval is zero, so this only waits on `slow_down`.
+async fn device_login(server: &MockServer) -> Result<GmailLabelRequest, Error> {
+    let config = server.configuration(test_support::free_port()?);
+    let request = GmailLabelRequest::new(&config, server.transport())?
+        .oauth2_device_authorization()
+        .await?;
+    assert!(request.details().user_code().secret().starts_with("MOCK-"));
+    request.oauth2_device_token().await
+}
+
+#[tokio::test]
+async fn device_flow_polls_through_authorization_pending() {
+    let server = MockServer::start().await.unwrap();
+
+    let request = device_login(&server).await.unwrap();
+    assert!(request.token().refresh_token.is_some());
+    assert!(request.get_labels().await.is_ok());
+}
+
+#[tokio::test]
+async fn device_flow_backs_off_on_slow_down() {
+    let server = MockServer::start().await.unwrap();
+    server.fail_with(Some(Failure::SlowDown));
+
+    let start = std::time::Instant::now();
+    device_login(&server).await.unwrap();
+    // oauth2 adds five seconds to the interval for each slow_down.
+    assert!(start.elapsed() >= std::time::Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn device_flow_expired_token_needs_reauthorization() {
+    let server = MockServer::start().await.unwrap();
+    server.fail_with(Some(Failure::ExpiredToken));
+
+    let error = device_login(&server).await.unwrap_err();
+    assert_eq!(error.kind(), "needs_reauth");
+    assert!(format!("{:?}", error).contains("expired_token"), "got {:?}", error);
+}
+
+#[tokio::test]
+async fn device_flow_access_denied_ends_polling() {
+    let server = MockServer::start().await.unwrap();
+    server.fail_with(Some(Failure::AccessDenied));
+
+    let error = device_login(&server).await.unwrap_err();
+    assert_eq!(error.kind(), "needs_reauth");
+    assert!(format!("{:?}", error).contains("access_denied"), "got {:?}", error);
+}
+
+#[tokio::test]
+async fn revoke_sends_the_refresh_token_first() {
+    let server = MockServer::start().await.unwrap();
+    let request = device_login(&server).await.unwrap();
+    let token = request.token().clone();
+
+    // The refresh token takes the access token with it, so the second one is
+    // invalid_token, which is just as revoked.
+    request.revoke().await.unwrap();
+    let refresh_token = token.refresh_token.as_ref().unwrap().secret();
+    assert_eq!(server.revoked(), [refresh_token.as_str(), token.access_token.secret()]);
+
+    let config = server.configuration(test_support::free_port().unwrap());
+    let revoked = GmailLabelRequest::new(&config, server.transport()).unwrap().with_token(token);
+    let error = revoked.get_labels().await.unwrap_err();
+    assert!(matches!(error, Error::Unauthorized), "got {:?}", error);
+}
+
+#[tokio::test]
+async fn revoking_twice_is_fine() {
+    let server = MockServer::start().await.unwrap();
+    let request = device_login(&server).await.unwrap();
+    let token = request.token().clone();
+    request.revoke().await.unwrap();
+
+    let config = server.configuration(test_support::free_port().unwrap());
+    let again = GmailLabelRequest::new(&config, server.transport())
+        .unwrap()
+        .with_token(token);
+    again.revoke().await.unwrap();
+    assert_eq!(server.revoked().len(), 4);
+}
+
+#[tokio::test]
+async fn revoke_without_a_refresh_token() {
+    let server = MockServer::start().await.unwrap();
+    let mut token = device_login(&server).await.unwrap().token().clone();
+    token.refresh_token = None;
+
+    let config = server.configuration(test_support::free_port().unwrap());
+    let request = GmailLabelRequest::new(&config, server.transport())
+        .unwrap()
+        .with_token(token.clone());
+    request.revoke().await.unwrap();
+    assert_eq!(server.revoked(), [token.access_token.secret().as_str()]);
+}
+
+/// Wait for the mock to have another request, then cancel. So it's a
+/// stalled request being cut short, not one that never started.
+async fn cancel_once_received(server: &MockServer, cancel: &CancellationToken) {
+    let before = server.received();
+    while server.received() == before {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    cancel.cancel();
+}
+
+#[tokio::test]
+async fn cancelling_stops_a_revoke() {
+    let server = MockServer::start().await.unwrap();
+    let token = device_login(&server).await.unwrap().token().clone();
+    server.fail_with(Some(Failure::Stalled));
+
+    let config = server.configuration(test_support::free_port().unwrap());
+    let request = GmailLabelRequest::new(&config, server.transport())
+        .unwrap()
+        .with_token(token);
+    let cancel = request.cancellation_token();
+    let revoke = tokio::spawn(request.revoke());
+    cancel_once_received(&server, &cancel).await;
+
+    let error = revoke.await.unwrap().unwrap_err();
+    assert!(matches!(error, Error::Cancelled), "got {:?}", error);
+}
+
+#[tokio::test]
+async fn cancelling_stops_a_token_exchange() {
+    let server = MockServer::start().await.unwrap();
+    server.fail_with(Some(Failure::Stalled));
+    let config = server.configuration(test_support::free_port().unwrap());
+    let request = GmailLabelRequest::new(&config, server.transport()).unwrap().authorize();
+    let cancel = request.cancellation_token();
+
+    let exchange = tokio::spawn(request.oauth2_token_verification("code".to_string()));
+    cancel_once_received(&server, &cancel).await;
+
+    let error = exchange.await.unwrap().unwrap_err();
+    assert!(matches!(error, Error::Cancelled), "got {:?}", error);
+}
+
+#[tokio::test]
+async fn cancelling_stops_device_polling() {
+    let server = MockServer::start().await.unwrap();
+    let config = server.configuration(test_support::free_port().unwrap());
+    let request = GmailLabelRequest::new(&config, server.transport())
+        .unwrap()
+        .oauth2_device_authorization()
+        .await
+        .unwrap();
+    server.fail_with(Some(Failure::Stalled));
+    let cancel = request.cancellation_token();
+
+    let device = tokio::spawn(request.oauth2_device_token());
+    cancel_once_received(&server, &cancel).await;
+
+    let error = device.await.unwrap().unwrap_err();
+    assert!(matches!(error, Error::Cancelled), "got {:?}", error);
+}
+
+#[tokio::test]
+async fn cancelling_stops_get_labels() {
+    let server = MockServer::start().await.unwrap();
+    let request = device_login(&server).await.unwrap();
+    server.fail_with(Some(Failure::Stalled));
+    let cancel = request.cancellation_token();
+
+    let labels = tokio::spawn(request.get_labels());
+    cancel_once_received(&server, &cancel).await;
+
+    let error = labels.await.unwrap().unwrap_err();
+    assert!(matches!(error, Error::Cancelled), "got {:?}", error);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fetch_labels_logs_in_without_a_stored_token() {
+    let server = MockServer::start().await.unwrap();
+    let config = server.configuration(test_support::free_port().unwrap());
+    let db = db::init_in_memory().unwrap();
+
+    // The listener only starts after the URL is handed over, so the browser
+    // keeps trying until it's there.
+    let labels = fetch::labels(&config, &db, |url| {
+        let url = url.clone();
+        tokio::spawn(async move {
+            while test_support::browse(&url).await.is_err() {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        });
+    })
+    .await
+    .unwrap();
+
+    let names: Vec<_> = labels.iter().map(|label| label.name.as_str()).collect();
+    assert_eq!(names, ["INBOX", "Work", "Work/Projects"]);
+    assert!(Token::load(&db, &config.profile).unwrap().is_some());
+}
+
+#[tokio::test]
+async fn fetch_labels_uses_the_stored_token() {
+    let server = MockServer::start().await.unwrap();
+    let config = server.configuration(test_support::free_port().unwrap());
+    let db = db::init_in_memory().unwrap();
+    let token = device_login(&server).await.unwrap().token().clone();
+    token.save(&db, &config.profile).unwrap();
+
+    let labels = fetch::labels(&config, &db, |_| panic!("asked to log in")).await.unwrap();
+    assert_eq!(labels.len(), 3);
+    let stored = Token::load(&db, &config.profile).unwrap().unwrap();
+    assert_eq!(stored.access_token.secret(), token.access_token.secret());
+}
+
+#[tokio::test]
+async fn fetch_labels_refreshes_an_expired_token() {
+    let server = MockServer::start().await.unwrap();
+    let config = server.configuration(test_support::free_port().unwrap());
+    let db = db::init_in_memory().unwrap();
+    let mut token = device_login(&server).await.unwrap().token().clone();
+    token.expires_at = Some(1);
+    token.save(&db, &config.profile).unwrap();
+
+    let labels = fetch::labels(&config, &db, |_| panic!("asked to log in")).await.unwrap();
+    assert_eq!(labels.len(), 3);
+    let stored = Token::load(&db, &config.profile).unwrap().unwrap();
+    assert_ne!(stored.access_token.secret(), token.access_token.secret());
+    assert!(!stored.is_expired());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fetch_labels_logs_in_again_when_the_refresh_is_refused() {
+    let server = MockServer::start().await.unwrap();
+    let config = server.configuration(test_support::free_port().unwrap());
+    let db = db::init_in_memory().unwrap();
+    let mut token = device_login(&server).await.unwrap().token().clone();
+    token.expires_at = Some(1);
+    token.save(&db, &config.profile).unwrap();
+    server.fail_with(Some(Failure::InvalidGrant));
+
+    // The user signs in again, and this time the code is good.
+    let mut opened = false;
+    let labels = fetch::labels(&config, &db, |url| {
+        opened = true;
+        server.fail_with(None);
+        let url = url.clone();
+        tokio::spawn(async move {
+            while test_support::browse(&url).await.is_err() {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        });
+    })
+    .await
+    .unwrap();
+
+    assert!(opened);
+    assert_eq!(labels.len(), 3);
+    let stored = Token::load(&db, &config.profile).unwrap().unwrap();
+    assert_ne!(stored.refresh_token.unwrap().secret(), token.refresh_token.unwrap().secret());
+}
@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+use rust_requester::error::Error;
+use rust_requester::quota::{GmailMethod, QuotaCosts, QuotaLimiter};
+
+// The limiter keeps time with `Instant`, so these run on the real clock,
+// with a small bucket to keep them quick. The bounds are loose on purpose.
+
+#[tokio::test]
+async fn burst_is_immediate_then_calls_wait() {
+    // labels.create costs 5, so two fill a bucket of 10.
+    let limiter = QuotaLimiter::new(10, 10, QuotaCosts::default());
+
+    let start = Instant::now();
+    limiter.acquire(GmailMethod::LabelsCreate).await.unwrap();
+    limiter.acquire(GmailMethod::LabelsCreate).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    // Five more units at ten a second is half a second.
+    limiter.acquire(GmailMethod::LabelsCreate).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(400), "{:?}", start.elapsed());
+}
+
+#[tokio::test]
+async fn bucket_refills_over_time() {
+    let limiter = QuotaLimiter::new(10, 50, QuotaCosts::default());
+    limiter.acquire(GmailMethod::LabelsCreate).await.unwrap();
+    limiter.acquire(GmailMethod::LabelsCreate).await.unwrap();
+
+    // 200ms at fifty a second puts all ten back.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let start = Instant::now();
+    limiter.acquire(GmailMethod::LabelsCreate).await.unwrap();
+    limiter.acquire(GmailMethod::LabelsCreate).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(50), "{:?}", start.elapsed());
+}
+
+#[tokio::test]
+async fn cost_larger_than_the_bucket_is_refused() {
+    let mut costs = QuotaCosts::default();
+    costs.set(GmailMethod::LabelsGet, 20);
+    let limiter = QuotaLimiter::new(10, 10, costs);
+
+    let error = limiter.acquire(GmailMethod::LabelsGet).await.unwrap_err();
+    assert!(matches!(error, Error::QuotaCostExceedsCapacity), "got {:?}", error);
+    assert_eq!(limiter.units_used(), 0);
+}
+
+#[tokio::test]
+async fn usage_is_counted_per_method() {
+    let limiter = QuotaLimiter::default();
+    limiter.acquire(GmailMethod::LabelsList).await.unwrap();
+    limiter.acquire(GmailMethod::LabelsGet).await.unwrap();
+    limiter.acquire(GmailMethod::LabelsGet).await.unwrap();
+    limiter.acquire(GmailMethod::LabelsDelete).await.unwrap();
+
+    let usage = limiter.usage();
+    assert_eq!(usage[&GmailMethod::LabelsGet], 2);
+    assert_eq!(usage[&GmailMethod::LabelsDelete], 5);
+    assert_eq!(limiter.units_used(), 8);
+}